@@ -0,0 +1,65 @@
+use std::path::PathBuf;
+use clap::{ArgGroup, Parser, Subcommand};
+use crate::entry::ExportFormat;
+
+/// Retrieve scheduled lives of virtual livers, and send them to API Server.
+///
+/// Runs the handlers when no subcommand is given.
+#[derive(Debug, Parser)]
+#[clap(version, about)]
+pub struct Cli {
+    #[clap(subcommand)]
+    pub command: Option<Command>
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Viewer counts sampled during live streams.
+    #[clap(subcommand)]
+    Samples(SamplesCommand),
+    /// Caches of etags and videos.
+    #[clap(subcommand)]
+    Cache(CacheCommand)
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SamplesCommand {
+    /// Write samples as CSV or JSON.
+    Export {
+        /// `csv` or `json`.
+        #[clap(long, default_value = "csv")]
+        format: ExportFormat,
+        /// Only samples of this video id.
+        #[clap(long)]
+        video: Option<String>,
+        /// Write to the file instead of stdout.
+        #[clap(long, short)]
+        out: Option<PathBuf>
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CacheCommand {
+    /// Cached videos with their states.
+    List {
+        /// Only videos of this affiliation.
+        #[clap(long)]
+        affiliation: Option<String>
+    },
+    /// Every cached entry of a video or channel id.
+    Show {
+        id: String
+    },
+    /// Remove cached videos of an affiliation, or every etag.
+    #[clap(group(ArgGroup::new("target").required(true).multiple(true).args(&["affiliation", "etags"])))]
+    Purge {
+        /// Videos and tombstones of this affiliation.
+        #[clap(long)]
+        affiliation: Option<String>,
+        /// Etags of search, videos and channels requests.
+        #[clap(long)]
+        etags: bool
+    },
+    /// Number of entries in each cache.
+    Stats
+}
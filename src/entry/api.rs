@@ -0,0 +1,267 @@
+use std::time::Duration;
+use once_cell::sync::OnceCell;
+use rand::Rng;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use tracing::warn;
+use crate::entry::cassette::{Cassette, get_cassette, REDACTED};
+use crate::entry::keys::{get_api_key_pool, KeyPoolError};
+use crate::entry::quota::{Endpoint, get_quota_ledger, Priority, QuotaError};
+use crate::entry::scheduler::get_scheduler;
+
+fn get_retry_policy() -> &'static RetryPolicy {
+    static POLICY: OnceCell<RetryPolicy> = OnceCell::new();
+    POLICY.get_or_init(|| {
+        let max_retries = dotenv::var("REQUEST_MAX_RETRIES")
+            .ok()
+            .and_then(|f| f.parse().ok())
+            .unwrap_or(3);
+        let base = dotenv::var("REQUEST_RETRY_BASE_MS")
+            .ok()
+            .and_then(|f| f.parse().ok())
+            .map(Duration::from_millis)
+            .unwrap_or_else(|| Duration::from_millis(500));
+        RetryPolicy::new(max_retries, base, Duration::from_secs(30))
+    })
+}
+
+/// Failure of a YouTube Data API request.
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum YoutubeApiError {
+    #[error("request timed out: {}", .0)]
+    Timeout(String),
+    #[error("network error: {}", .0)]
+    Network(String),
+    /// Error response of the API, `reason` and `domain` are taken from the first entry of `error.errors`.
+    #[error("{} {} ({}): {}", .status, .reason, .domain, .message)]
+    Api { status: StatusCode, reason: String, domain: String, message: String },
+    #[error("cannot parse response: {}", .0)]
+    Parse(String),
+    #[error("no recorded response for {}", .0)]
+    NotRecorded(String),
+    #[error(transparent)]
+    Quota(#[from] QuotaError),
+    #[error(transparent)]
+    Keys(#[from] KeyPoolError),
+}
+
+/// `{"error": {"code": 403, "message": "...", "errors": [{"message": "...", "domain": "youtube.quota", "reason": "quotaExceeded"}]}}`
+#[derive(Debug, Clone, Deserialize)]
+struct ErrorResponse {
+    error: ErrorBody
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ErrorBody {
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    errors: Vec<ErrorDetail>
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ErrorDetail {
+    #[serde(default)]
+    reason: String,
+    #[serde(default)]
+    domain: String
+}
+
+impl YoutubeApiError {
+    pub fn from_response(status: StatusCode, body: &str) -> YoutubeApiError {
+        match serde_json::from_str::<ErrorResponse>(body) {
+            Ok(ErrorResponse { error }) => {
+                let detail = error.errors.into_iter().next();
+                YoutubeApiError::Api {
+                    status,
+                    reason: detail.as_ref().map(|detail| detail.reason.clone()).unwrap_or_default(),
+                    domain: detail.map(|detail| detail.domain).unwrap_or_default(),
+                    message: error.message
+                }
+            },
+            Err(_) => YoutubeApiError::Api {
+                status,
+                reason: String::new(),
+                domain: String::new(),
+                message: body.chars().take(256).collect()
+            }
+        }
+    }
+
+    pub fn reason(&self) -> Option<&str> {
+        match self {
+            YoutubeApiError::Api { reason, .. } => Some(reason),
+            _ => None
+        }
+    }
+
+    /// The key has run out of its daily quota, and another key may succeed.
+    pub fn is_quota_exceeded(&self) -> bool {
+        matches!(self.reason(), Some("quotaExceeded" | "dailyLimitExceeded"))
+    }
+
+    /// Nothing can be requested for the rest of the run.
+    pub fn is_exhausted(&self) -> bool {
+        matches!(self, YoutubeApiError::Quota(QuotaError::Exhausted { .. }) | YoutubeApiError::Keys(_))
+    }
+
+    /// Worth retrying the same request after a while.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            YoutubeApiError::Timeout(_) | YoutubeApiError::Network(_) => true,
+            YoutubeApiError::Api { status, reason, .. } => status.is_server_error()
+                || *status == StatusCode::TOO_MANY_REQUESTS
+                || matches!(reason.as_str(), "rateLimitExceeded" | "userRateLimitExceeded" | "backendError"),
+            _ => false
+        }
+    }
+}
+
+impl From<reqwest::Error> for YoutubeApiError {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_timeout() {
+            YoutubeApiError::Timeout(error.to_string())
+        } else if error.is_decode() {
+            YoutubeApiError::Parse(error.to_string())
+        } else {
+            YoutubeApiError::Network(error.to_string())
+        }
+    }
+}
+
+/// Exponential backoff with jitter.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    base: Duration,
+    cap: Duration
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32, base: Duration, cap: Duration) -> RetryPolicy {
+        Self { max_retries, base, cap }
+    }
+
+    /// Random delay between the half and the whole of `base * 2^attempt`, capped by `cap`.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self.base
+            .checked_mul(2u32.saturating_pow(attempt))
+            .unwrap_or(self.cap)
+            .min(self.cap);
+        let millis = delay.as_millis() as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(millis / 2..=millis))
+    }
+}
+
+/// Send a request built with a key from the pool.
+///
+/// Every attempt is charged to the quota ledger, since YouTube charges failed requests too.
+/// - `403 quotaExceeded` cools the key down and sends again with the next key.
+/// - Timeouts, `5xx` and rate limits are retried by `REQUEST_MAX_RETRIES` (default: 3)
+///   with exponential backoff from `REQUEST_RETRY_BASE_MS` (default: 500ms).
+/// - Other errors are returned at once.
+///
+/// Every attempt waits for the shared [`Scheduler`](crate::entry::scheduler::Scheduler) before sent.
+///
+/// With `CASSETTE_MODE=record` every response is also written to the cassette,
+/// and with `replay` the recorded responses are returned instead, without key and quota.
+///
+/// Returns the response only when it is `2xx` or `304 Not Modified`.
+pub(super) async fn send<F>(endpoint: Endpoint, priority: Priority, request: F) -> Result<Response, YoutubeApiError>
+    where F: Fn(&str) -> RequestBuilder
+{
+    let policy = get_retry_policy();
+    let pool = get_api_key_pool();
+    let cassette = get_cassette();
+    let replaying = cassette.map(Cassette::is_replaying).unwrap_or(false);
+    let mut attempt = 0;
+    loop {
+        let key = if replaying {
+            REDACTED
+        } else {
+            // a request is charged only once it has a key to be sent with.
+            let key = pool.acquire()?;
+            get_quota_ledger().try_spend(endpoint, priority)?;
+            key
+        };
+        let permit = match replaying {
+            true => None,
+            false => Some(get_scheduler().acquire().await)
+        };
+        let sent = match cassette {
+            Some(cassette) if replaying => cassette.replay(request(key)),
+            Some(cassette) => match request(key).send().await {
+                Ok(res) => cassette.record(&res.url().clone(), res).await,
+                Err(error) => Err(error.into())
+            },
+            None => request(key).send().await.map_err(YoutubeApiError::from)
+        };
+        drop(permit);
+        let error = match sent {
+            Ok(res) if res.status().is_success() || res.status() == StatusCode::NOT_MODIFIED => {
+                if !replaying {
+                    pool.record(key, endpoint.cost());
+                }
+                return Ok(res)
+            },
+            Ok(res) => {
+                if !replaying {
+                    pool.record(key, endpoint.cost());
+                }
+                let status = res.status();
+                let body = res.text().await.unwrap_or_default();
+                YoutubeApiError::from_response(status, &body)
+            },
+            Err(error) => error
+        };
+        if error.is_quota_exceeded() && !replaying {
+            pool.exhaust(key);
+            continue
+        }
+        if !error.is_transient() || attempt >= policy.max_retries {
+            return Err(error)
+        }
+        let delay = if replaying { Duration::ZERO } else { policy.backoff(attempt) };
+        attempt += 1;
+        warn!("{} failed: {}, retry {}/{} after {}ms", endpoint.as_str(), error, attempt, policy.max_retries, delay.as_millis());
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod api_test {
+    use std::time::Duration;
+    use reqwest::StatusCode;
+    use crate::entry::api::{RetryPolicy, YoutubeApiError};
+
+    #[test]
+    fn error_parse_test() {
+        let body = r#"{"error": {"code": 403, "message": "The request cannot be completed because you have exceeded your quota.",
+            "errors": [{"message": "The request cannot be completed because you have exceeded your quota.", "domain": "youtube.quota", "reason": "quotaExceeded"}]}}"#;
+        let error = YoutubeApiError::from_response(StatusCode::FORBIDDEN, body);
+        assert_eq!(error.reason(), Some("quotaExceeded"));
+        assert!(error.is_quota_exceeded());
+        assert!(!error.is_transient());
+
+        let error = YoutubeApiError::from_response(StatusCode::NOT_FOUND,
+            r#"{"error": {"code": 404, "message": "not found", "errors": [{"domain": "youtube.playlistItem", "reason": "playlistNotFound"}]}}"#);
+        assert!(matches!(&error, YoutubeApiError::Api { domain, .. } if domain == "youtube.playlistItem"));
+        assert!(!error.is_transient());
+
+        let error = YoutubeApiError::from_response(StatusCode::SERVICE_UNAVAILABLE, "<html>unavailable</html>");
+        assert!(error.is_transient());
+        assert_eq!(error.reason(), Some(""));
+    }
+
+    #[test]
+    fn backoff_test() {
+        let policy = RetryPolicy::new(3, Duration::from_millis(100), Duration::from_secs(1));
+        for _ in 0..32 {
+            let first = policy.backoff(0);
+            assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+            let third = policy.backoff(2);
+            assert!(third >= Duration::from_millis(200) && third <= Duration::from_millis(400));
+            assert!(policy.backoff(31) <= Duration::from_secs(1));
+        }
+    }
+}
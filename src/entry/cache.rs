@@ -0,0 +1,636 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use async_trait::async_trait;
+use chrono::Local;
+use once_cell::sync::OnceCell;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use tracing::{error, info, warn};
+use crate::entry::cache_path;
+
+/// Keys of every cache.
+pub trait Key: Clone + Eq + Hash + Serialize + DeserializeOwned + Send + Sync + 'static {}
+
+impl<T: Clone + Eq + Hash + Serialize + DeserializeOwned + Send + Sync + 'static> Key for T {}
+
+/// Values of every cache.
+pub trait Value: Clone + Serialize + DeserializeOwned + Send + Sync + 'static {}
+
+impl<T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static> Value for T {}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CacheBackend {
+    Json,
+    Sqlite
+}
+
+impl FromStr for CacheBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(CacheBackend::Json),
+            "sqlite" => Ok(CacheBackend::Sqlite),
+            other => Err(format!("unknown cache backend: {}", other))
+        }
+    }
+}
+
+/// `CACHE_BACKEND=json` (default) or `sqlite`.
+pub fn get_cache_backend() -> &'static CacheBackend {
+    static BACKEND: OnceCell<CacheBackend> = OnceCell::new();
+    BACKEND.get_or_init(|| {
+        let backend = dotenv::var("CACHE_BACKEND")
+            .ok()
+            .and_then(|backend| backend.parse().ok())
+            .unwrap_or(CacheBackend::Json);
+        info!(backend = ?backend, "caches are stored in {}", match backend {
+            CacheBackend::Json => cache_path("*.json"),
+            CacheBackend::Sqlite => get_cache_database().to_string()
+        });
+        backend
+    })
+}
+
+/// `salmon.sqlite3` in the cache directory, or `CACHE_DATABASE`.
+pub fn get_cache_database() -> &'static str {
+    static DATABASE: OnceCell<String> = OnceCell::new();
+    DATABASE.get_or_init(|| {
+        dotenv::var("CACHE_DATABASE")
+            .unwrap_or_else(|_| cache_path("salmon.sqlite3"))
+    })
+}
+
+fn get_database() -> &'static Database {
+    static DATABASE: OnceCell<Database> = OnceCell::new();
+    DATABASE.get_or_init(|| Database::open(get_cache_database())
+        .expect("cannot open cache database"))
+}
+
+/// Cache named `name` in the configured backend.
+///
+/// Every call with the same name in the process shares the same instance,
+/// so that concurrent handlers never overwrite each other's entries.
+/// A cache must always be opened with the same key and value types.
+pub fn open_cache<K: Key, V: Value>(name: &str) -> Arc<dyn Cache<K, V>> {
+    static OPENED: OnceCell<Mutex<HashMap<String, Arc<dyn Any + Send + Sync>>>> = OnceCell::new();
+    let mut opened = OPENED.get_or_init(Default::default).lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(cache) = opened.get(name) {
+        match Arc::clone(cache).downcast::<Arc<dyn Cache<K, V>>>() {
+            Ok(cache) => return Arc::clone(&cache),
+            Err(_) => warn!("{} cache is opened with other types, it is no longer shared.", name)
+        }
+    }
+    let cache: Arc<dyn Cache<K, V>> = match get_cache_backend() {
+        CacheBackend::Json => Arc::new(JsonCache::load(cache_path(format!("{}.json", name)))),
+        CacheBackend::Sqlite => Arc::new(get_database().namespace(name))
+    };
+    opened.insert(name.to_string(), Arc::new(Arc::clone(&cache)));
+    cache
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CacheError {
+    #[error("cache database error: {}", .0)]
+    Database(#[from] rusqlite::Error),
+    #[error("cannot serialize cache entry: {}", .0)]
+    Serialize(#[from] serde_json::Error),
+    #[error("cache database task failed: {}", .0)]
+    Task(#[from] tokio::task::JoinError),
+    #[error("cannot write cache file: {}", .0)]
+    Io(#[from] std::io::Error),
+    #[error("cache format version {} is newer than {}", .0, CACHE_FORMAT_VERSION)]
+    Version(u32)
+}
+
+impl CacheError {
+    /// The file is not a cache database this build can read.
+    fn is_unreadable(&self) -> bool {
+        match self {
+            CacheError::Database(rusqlite::Error::SqliteFailure(error, _)) =>
+                matches!(error.code, rusqlite::ErrorCode::NotADatabase | rusqlite::ErrorCode::DatabaseCorrupt),
+            CacheError::Version(_) => true,
+            _ => false
+        }
+    }
+}
+
+/// Key-value store behind every cache of salmon.
+///
+/// Failures are logged and treated as a miss, same as a broken cache file has always been.
+#[async_trait]
+pub trait Cache<K: Key, V: Value>: Send + Sync {
+    async fn find_value(&self, key: &K) -> Option<V>;
+
+    async fn all_items(&self) -> Vec<(K, V)>;
+
+    /// Insert or replace `items` at once.
+    async fn put_all(&self, items: Vec<(K, V)>);
+
+    /// Remove `keys` at once.
+    async fn remove(&self, keys: &[K]);
+
+    async fn put(&self, key: K, value: V) {
+        self.put_all(vec![(key, value)]).await
+    }
+}
+
+/// Version of the cache format written by this build.
+///
+/// - 0: bare array of entries written by `misery_rs`, without header.
+/// - 1: `{"version": 1, "items": [{"key": .., "value": ..}]}` in JSON files, `user_version = 1` in SQLite.
+pub const CACHE_FORMAT_VERSION: u32 = 1;
+
+/// Migration of an entry `{"key": .., "value": ..}` from the version at the index to the next one.
+///
+/// Fields added with `#[serde(default)]` need no migration, renamed or reshaped ones do.
+const MIGRATIONS: [fn(serde_json::Value) -> serde_json::Value; CACHE_FORMAT_VERSION as usize] = [
+    // 0 -> 1: only the header is added.
+    std::convert::identity
+];
+
+/// Tables of SQLite created at the version at the index.
+const SCHEMA: [&str; CACHE_FORMAT_VERSION as usize] = ["
+    CREATE TABLE IF NOT EXISTS cache (
+        name TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (name, key)
+    );
+    CREATE TABLE IF NOT EXISTS quarantine (
+        name TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        reason TEXT NOT NULL,
+        quarantined_at TEXT NOT NULL
+    );
+"];
+
+fn migrate(entry: serde_json::Value, from: u32) -> serde_json::Value {
+    MIGRATIONS[from as usize..].iter()
+        .fold(entry, |entry, migration| migration(entry))
+}
+
+/// `path` with a timestamp and `.corrupt`, next to it.
+fn quarantine_path(path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.{}.corrupt", path.display(), Local::now().format("%Y%m%d%H%M%S")))
+}
+
+/// Move the unreadable file aside, so that it can be looked into or restored.
+fn quarantine(path: &Path, reason: impl Display) {
+    let moved = quarantine_path(path);
+    match std::fs::rename(path, &moved) {
+        Ok(_) => warn!("{} is unreadable ({}), moved to {}.", path.display(), reason, moved.display()),
+        Err(error) => error!("{} is unreadable ({}), and cannot be moved: {}", path.display(), reason, error)
+    }
+}
+
+fn write_atomic(path: &Path, buf: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temporary = PathBuf::from(format!("{}.tmp", path.display()));
+    std::fs::write(&temporary, buf)?;
+    std::fs::rename(&temporary, path)
+}
+
+#[derive(Serialize, Deserialize)]
+struct Document<E> {
+    version: u32,
+    items: Vec<E>
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry<K, V> {
+    key: K,
+    value: V
+}
+
+/// JSON file with a version header.
+///
+/// Files of older versions are migrated when loaded. A file which cannot be read is moved aside as `*.corrupt`,
+/// and so are entries which cannot be read, with a warning instead of a crash or a silent reset.
+/// Written to a temporary file first, then renamed over, so that a crash never leaves a half written file.
+pub struct JsonCache<K, V> {
+    path: PathBuf,
+    items: RwLock<HashMap<K, V>>
+}
+
+impl<K: Key, V: Value> JsonCache<K, V> {
+    pub fn load(path: impl Into<String>) -> JsonCache<K, V> {
+        let path = PathBuf::from(path.into());
+        let (items, outdated) = Self::read(&path);
+        if outdated {
+            if let Err(reason) = Self::persist(&path, &items) {
+                error!("cannot write {}: {}", path.display(), reason);
+            }
+        }
+        Self { path, items: RwLock::new(items) }
+    }
+
+    /// Entries migrated to [`CACHE_FORMAT_VERSION`], and whether the file has to be written again.
+    fn read(path: &Path) -> (HashMap<K, V>, bool) {
+        let buf = match std::fs::read(path) {
+            Ok(buf) => buf,
+            Err(reason) if reason.kind() == std::io::ErrorKind::NotFound => return (HashMap::new(), false),
+            Err(reason) => {
+                error!("cannot read {}: {}", path.display(), reason);
+                return (HashMap::new(), false)
+            }
+        };
+        let (version, entries) = match serde_json::from_slice::<serde_json::Value>(&buf)
+            .and_then(|document| match document {
+                serde_json::Value::Array(entries) => Ok((0, entries)),
+                document => serde_json::from_value::<Document<serde_json::Value>>(document)
+                    .map(|document| (document.version, document.items))
+            }) {
+            Ok(read) => read,
+            Err(reason) => {
+                quarantine(path, reason);
+                return (HashMap::new(), false)
+            }
+        };
+        if version > CACHE_FORMAT_VERSION {
+            quarantine(path, CacheError::Version(version));
+            return (HashMap::new(), false)
+        }
+
+        let mut items = HashMap::new();
+        let mut unreadable = Vec::new();
+        for entry in entries {
+            match serde_json::from_value::<Entry<K, V>>(migrate(entry.clone(), version)) {
+                Ok(Entry { key, value }) => { items.insert(key, value); },
+                Err(_) => unreadable.push(entry)
+            }
+        }
+        if !unreadable.is_empty() {
+            let moved = quarantine_path(path);
+            match serde_json::to_vec(&Document { version, items: unreadable.iter().collect() }).map_err(CacheError::from)
+                .and_then(|buf| Ok(write_atomic(&moved, &buf)?)) {
+                Ok(_) => warn!("{} entries of {} are unreadable, moved to {}.", unreadable.len(), path.display(), moved.display()),
+                Err(reason) => error!("{} entries of {} are unreadable, and cannot be moved: {}", unreadable.len(), path.display(), reason)
+            }
+        }
+        if version < CACHE_FORMAT_VERSION {
+            info!("{} is migrated from version {} to {}.", path.display(), version, CACHE_FORMAT_VERSION);
+        }
+        (items, version < CACHE_FORMAT_VERSION || !unreadable.is_empty())
+    }
+
+    fn persist(path: &Path, items: &HashMap<K, V>) -> Result<(), CacheError> {
+        let document = Document {
+            version: CACHE_FORMAT_VERSION,
+            items: items.iter()
+                .map(|(key, value)| Entry { key, value })
+                .collect()
+        };
+        Ok(write_atomic(path, &serde_json::to_vec(&document)?)?)
+    }
+}
+
+#[async_trait]
+impl<K: Key, V: Value> Cache<K, V> for JsonCache<K, V> {
+    async fn find_value(&self, key: &K) -> Option<V> {
+        self.items.read().await.get(key).cloned()
+    }
+
+    async fn all_items(&self) -> Vec<(K, V)> {
+        self.items.read().await.iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    async fn put_all(&self, items: Vec<(K, V)>) {
+        let mut cached = self.items.write().await;
+        cached.extend(items);
+        if let Err(reason) = Self::persist(&self.path, &cached) {
+            error!("cannot write {}: {}", self.path.display(), reason);
+        }
+    }
+
+    async fn remove(&self, keys: &[K]) {
+        let mut cached = self.items.write().await;
+        let before = cached.len();
+        for key in keys {
+            cached.remove(key);
+        }
+        if cached.len() == before {
+            return
+        }
+        if let Err(reason) = Self::persist(&self.path, &cached) {
+            error!("cannot write {}: {}", self.path.display(), reason);
+        }
+    }
+}
+
+/// SQLite database holding every cache in a table, keyed by the cache name.
+///
+/// Writes are done in a transaction, and the database is opened in WAL mode,
+/// so that another process such as the `cache` command can read it while handlers are running.
+#[derive(Clone)]
+pub struct Database {
+    conn: Arc<Mutex<Connection>>
+}
+
+impl Database {
+    /// Open the database at `path`, which is moved aside if it is not a readable cache database.
+    pub fn open(path: impl AsRef<Path>) -> Result<Database, CacheError> {
+        let path = path.as_ref();
+        match Self::try_open(path) {
+            Err(reason) if reason.is_unreadable() => {
+                quarantine(path, &reason);
+                for suffix in ["-wal", "-shm"] {
+                    let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+                }
+                Self::try_open(path)
+            },
+            opened => opened
+        }
+    }
+
+    fn try_open(path: &Path) -> Result<Database, CacheError> {
+        if let Some(parent) = path.parent() {
+            // opening reports the error if the directory cannot be created.
+            let _ = std::fs::create_dir_all(parent);
+        }
+        let mut conn = Connection::open(path)?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        let version = conn.pragma_query_value(None, "user_version", |row| row.get::<_, u32>(0))?;
+        if version > CACHE_FORMAT_VERSION {
+            return Err(CacheError::Version(version))
+        }
+        for from in version..CACHE_FORMAT_VERSION {
+            Self::migrate(&mut conn, from)?;
+        }
+        Ok(Self { conn: Arc::new(Mutex::new(conn)) })
+    }
+
+    /// Migrate tables and entries from `from` to the next version in a transaction.
+    fn migrate(conn: &mut Connection, from: u32) -> Result<(), CacheError> {
+        let tx = conn.transaction()?;
+        tx.execute_batch(SCHEMA[from as usize])?;
+        let rows = tx.prepare("SELECT name, key, value FROM cache")?
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+        for (name, key, value) in rows {
+            let entry = serde_json::from_str::<serde_json::Value>(&key)
+                .and_then(|key| Ok(serde_json::json!({ "key": key, "value": serde_json::from_str::<serde_json::Value>(&value)? })))
+                .map(|entry| MIGRATIONS[from as usize](entry))
+                .and_then(serde_json::from_value::<Entry<serde_json::Value, serde_json::Value>>);
+            match entry {
+                Ok(entry) => tx.execute("UPDATE cache SET value = ?3 WHERE name = ?1 AND key = ?2",
+                    params![name, key, serde_json::to_string(&entry.value)?])?,
+                Err(reason) => move_to_quarantine(&tx, &name, &key, &value, &reason.to_string())?
+            };
+        }
+        tx.pragma_update(None, "user_version", from + 1)?;
+        tx.commit()?;
+        info!("cache database is migrated from version {} to {}.", from, from + 1);
+        Ok(())
+    }
+
+    pub fn namespace<K, V>(&self, name: impl Into<String>) -> SqliteCache<K, V> {
+        SqliteCache { database: self.clone(), name: name.into(), _marker: Default::default() }
+    }
+
+    /// Run `f` on the blocking thread pool with the connection.
+    async fn run<T: Send + 'static>(&self, f: impl FnOnce(&mut Connection) -> Result<T, CacheError> + Send + 'static) -> Result<T, CacheError> {
+        let conn = Arc::clone(&self.conn);
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            f(&mut conn)
+        }).await?
+    }
+}
+
+fn move_to_quarantine(tx: &rusqlite::Transaction, name: &str, key: &str, value: &str, reason: &str) -> rusqlite::Result<usize> {
+    tx.execute("INSERT INTO quarantine (name, key, value, reason, quarantined_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![name, key, value, reason, Local::now().to_rfc3339()])?;
+    tx.execute("DELETE FROM cache WHERE name = ?1 AND key = ?2", params![name, key])
+}
+
+/// A cache in [`Database`], keys and values are stored as JSON text.
+///
+/// Entries which cannot be read are moved to `quarantine` table with a warning.
+pub struct SqliteCache<K, V> {
+    database: Database,
+    name: String,
+    _marker: std::marker::PhantomData<fn() -> (K, V)>
+}
+
+impl<K: Key, V: Value> SqliteCache<K, V> {
+    async fn try_find_value(&self, key: &K) -> Result<Option<V>, CacheError> {
+        let name = self.name.clone();
+        let key = serde_json::to_string(key)?;
+        let found = key.clone();
+        let value = self.database.run(move |conn| Ok(conn
+            .query_row("SELECT value FROM cache WHERE name = ?1 AND key = ?2", params![name, found], |row| row.get::<_, String>(0))
+            .optional()?)).await?;
+        match value.map(|value| serde_json::from_str(&value).map_err(|reason| (value, reason))).transpose() {
+            Ok(value) => Ok(value),
+            Err((value, reason)) => {
+                self.quarantine(vec![(key, value, reason.to_string())]).await?;
+                Ok(None)
+            }
+        }
+    }
+
+    async fn try_all_items(&self) -> Result<Vec<(K, V)>, CacheError> {
+        let name = self.name.clone();
+        let rows = self.database.run(move |conn| {
+            let mut stmt = conn.prepare_cached("SELECT key, value FROM cache WHERE name = ?1 ORDER BY key")?;
+            let rows = stmt.query_map(params![name], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(rows)
+        }).await?;
+        let mut items = Vec::new();
+        let mut unreadable = Vec::new();
+        for (key, value) in rows {
+            match serde_json::from_str::<K>(&key).and_then(|read| Ok((read, serde_json::from_str::<V>(&value)?))) {
+                Ok(item) => items.push(item),
+                Err(reason) => unreadable.push((key, value, reason.to_string()))
+            }
+        }
+        self.quarantine(unreadable).await?;
+        Ok(items)
+    }
+
+    async fn quarantine(&self, rows: Vec<(String, String, String)>) -> Result<(), CacheError> {
+        if rows.is_empty() {
+            return Ok(())
+        }
+        warn!("{} entries of {} cache are unreadable, moved to quarantine table.", rows.len(), self.name);
+        let name = self.name.clone();
+        self.database.run(move |conn| {
+            let tx = conn.transaction()?;
+            for (key, value, reason) in rows {
+                move_to_quarantine(&tx, &name, &key, &value, &reason)?;
+            }
+            Ok(tx.commit()?)
+        }).await
+    }
+
+    async fn try_put_all(&self, items: Vec<(K, V)>) -> Result<(), CacheError> {
+        let name = self.name.clone();
+        let rows = items.iter()
+            .map(|(key, value)| Ok((serde_json::to_string(key)?, serde_json::to_string(value)?)))
+            .collect::<Result<Vec<_>, serde_json::Error>>()?;
+        self.database.run(move |conn| {
+            let tx = conn.transaction()?;
+            {
+                let mut stmt = tx.prepare_cached("INSERT OR REPLACE INTO cache (name, key, value) VALUES (?1, ?2, ?3)")?;
+                for (key, value) in rows {
+                    stmt.execute(params![name, key, value])?;
+                }
+            }
+            Ok(tx.commit()?)
+        }).await
+    }
+
+    async fn try_remove(&self, keys: &[K]) -> Result<(), CacheError> {
+        let name = self.name.clone();
+        let keys = keys.iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()?;
+        self.database.run(move |conn| {
+            let tx = conn.transaction()?;
+            {
+                let mut stmt = tx.prepare_cached("DELETE FROM cache WHERE name = ?1 AND key = ?2")?;
+                for key in keys {
+                    stmt.execute(params![name, key])?;
+                }
+            }
+            Ok(tx.commit()?)
+        }).await
+    }
+}
+
+#[async_trait]
+impl<K: Key, V: Value> Cache<K, V> for SqliteCache<K, V> {
+    async fn find_value(&self, key: &K) -> Option<V> {
+        self.try_find_value(key).await
+            .unwrap_or_else(|reason| {
+                error!("cannot read {} cache: {}", self.name, reason);
+                None
+            })
+    }
+
+    async fn all_items(&self) -> Vec<(K, V)> {
+        self.try_all_items().await
+            .unwrap_or_else(|reason| {
+                error!("cannot read {} cache: {}", self.name, reason);
+                Vec::new()
+            })
+    }
+
+    async fn put_all(&self, items: Vec<(K, V)>) {
+        if let Err(reason) = self.try_put_all(items).await {
+            error!("cannot write {} cache: {}", self.name, reason);
+        }
+    }
+
+    async fn remove(&self, keys: &[K]) {
+        if let Err(reason) = self.try_remove(keys).await {
+            error!("cannot remove entries from {} cache: {}", self.name, reason);
+        }
+    }
+}
+
+#[cfg(test)]
+mod cache_test {
+    use std::path::Path;
+    use std::sync::Arc;
+    use crate::entry::cache::{Cache, CACHE_FORMAT_VERSION, Database, JsonCache};
+
+    async fn exercise(cache: Arc<dyn Cache<String, Vec<i64>>>) {
+        cache.put(String::from("first"), vec![1]).await;
+        cache.put_all(vec![(String::from("second"), vec![2]), (String::from("first"), vec![1, 1])]).await;
+        assert_eq!(cache.find_value(&String::from("first")).await, Some(vec![1, 1]));
+        assert_eq!(cache.all_items().await.len(), 2);
+        cache.remove(&[String::from("first"), String::from("never")]).await;
+        assert_eq!(cache.find_value(&String::from("first")).await, None);
+        assert_eq!(cache.all_items().await, vec![(String::from("second"), vec![2])]);
+    }
+
+    #[tokio::test]
+    async fn backend_test() {
+        let dir = std::env::temp_dir();
+        let json = dir.join(format!("salmon_cache_test_{}.json", std::process::id())).to_string_lossy().to_string();
+        let database = dir.join(format!("salmon_cache_test_{}.sqlite3", std::process::id()));
+        let _ = std::fs::remove_file(&json);
+        let _ = std::fs::remove_file(&database);
+
+        exercise(Arc::new(JsonCache::load(json.as_str()))).await;
+        let reloaded: JsonCache<String, Vec<i64>> = JsonCache::load(json.as_str());
+        assert_eq!(reloaded.all_items().await.len(), 1);
+
+        let db = Database::open(&database).expect("");
+        exercise(Arc::new(db.namespace("numbers"))).await;
+        // caches are separated by the name, and persisted.
+        let other: Arc<dyn Cache<String, Vec<i64>>> = Arc::new(db.namespace("others"));
+        assert!(other.all_items().await.is_empty());
+        let reopened = Database::open(&database).expect("").namespace::<String, Vec<i64>>("numbers");
+        assert_eq!(reopened.find_value(&String::from("second")).await, Some(vec![2]));
+
+        let _ = std::fs::remove_file(&json);
+        let _ = std::fs::remove_file(&database);
+    }
+
+    fn quarantined(dir: &Path) -> usize {
+        std::fs::read_dir(dir).expect("").filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().ends_with(".corrupt"))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn version_test() {
+        let dir = std::env::temp_dir().join(format!("salmon_cache_version_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("");
+
+        // files without header are migrated, and unreadable entries are moved aside.
+        let legacy = dir.join("legacy.json");
+        std::fs::write(&legacy, r#"[{"key": "first", "value": [1]}, {"key": "broken", "value": "1"}]"#).expect("");
+        let cache: JsonCache<String, Vec<i64>> = JsonCache::load(legacy.to_string_lossy());
+        assert_eq!(cache.all_items().await, vec![(String::from("first"), vec![1])]);
+        let written = serde_json::from_str::<serde_json::Value>(&std::fs::read_to_string(&legacy).expect("")).expect("");
+        assert_eq!(written["version"], CACHE_FORMAT_VERSION);
+        assert_eq!(quarantined(&dir), 1);
+
+        // unreadable files and files of newer versions are moved aside, not overwritten.
+        for (name, content) in [("broken.json", "{ not json"), ("newer.json", r#"{"version": 999, "items": []}"#)] {
+            let path = dir.join(name);
+            std::fs::write(&path, content).expect("");
+            let cache: JsonCache<String, Vec<i64>> = JsonCache::load(path.to_string_lossy());
+            assert!(cache.all_items().await.is_empty());
+            assert!(!path.exists());
+        }
+        assert_eq!(quarantined(&dir), 3);
+
+        let database = dir.join("cache.sqlite3");
+        std::fs::write(&database, vec![7u8; 4096]).expect("");
+        let db = Database::open(&database).expect("");
+        assert_eq!(quarantined(&dir), 4);
+        let cache = db.namespace::<String, Vec<i64>>("numbers");
+        cache.put(String::from("first"), vec![1]).await;
+        db.conn.lock().unwrap().execute("INSERT INTO cache (name, key, value) VALUES ('numbers', '\"broken\"', '\"1\"')", []).expect("");
+        assert_eq!(cache.all_items().await.len(), 1);
+        let moved: i64 = db.conn.lock().unwrap().query_row("SELECT COUNT(*) FROM quarantine", [], |row| row.get(0)).expect("");
+        assert_eq!(moved, 1);
+        let version: u32 = db.conn.lock().unwrap().pragma_query_value(None, "user_version", |row| row.get(0)).expect("");
+        assert_eq!(version, CACHE_FORMAT_VERSION);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+use hyper::http;
+use once_cell::sync::OnceCell;
+use reqwest::{RequestBuilder, Response, Url};
+use reqwest::header::ETAG;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use crate::entry::api::YoutubeApiError;
+
+/// Value of `key` parameter in recorded requests, and the key sent while replaying.
+pub const REDACTED: &str = "REDACTED";
+
+/// `CASSETTE_MODE=record` or `replay` with `CASSETTE_PATH` (default: `./.cassettes/youtube.json`).
+pub fn get_cassette() -> Option<&'static Cassette> {
+    static CASSETTE: OnceCell<Option<Cassette>> = OnceCell::new();
+    CASSETTE.get_or_init(|| {
+        let mode = dotenv::var("CASSETTE_MODE")
+            .ok()
+            .and_then(|mode| mode.parse().ok())?;
+        let path = dotenv::var("CASSETTE_PATH")
+            .unwrap_or_else(|_| String::from("./.cassettes/youtube.json"));
+        info!(mode = ?mode, "youtube responses are {} {}", match mode { CassetteMode::Record => "recorded to", CassetteMode::Replay => "replayed from" }, path);
+        Some(Cassette::open(path, mode))
+    }).as_ref()
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CassetteMode {
+    Record,
+    Replay
+}
+
+impl FromStr for CassetteMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "record" => Ok(CassetteMode::Record),
+            "replay" => Ok(CassetteMode::Replay),
+            other => Err(format!("unknown cassette mode: {}", other))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Tape {
+    interactions: Vec<Interaction>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    /// Requested url with `key` redacted.
+    url: String,
+    status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+    /// Exact response body, kept as text to reproduce broken payloads.
+    body: String
+}
+
+/// Records YouTube responses into a JSON file, or replays them back.
+///
+/// Interactions are matched by the resource and the query except `key`, regardless of the host,
+/// and the same request is replayed in the recorded order. The last one is repeated when run out.
+#[derive(Debug)]
+pub struct Cassette {
+    path: PathBuf,
+    mode: CassetteMode,
+    tape: Mutex<Tape>,
+    cursor: Mutex<HashMap<String, usize>>
+}
+
+/// `videos?id=...&part=...` with sorted query, without host and `key`.
+fn request_key(url: &Url) -> String {
+    let resource = url.path().rsplit('/').next().unwrap_or_default();
+    let mut query = url.query_pairs()
+        .filter(|(name, _)| name != "key")
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>();
+    query.sort();
+    format!("{}?{}", resource, query.join("&"))
+}
+
+fn redact(url: &Url) -> Url {
+    let mut redacted = url.clone();
+    let pairs = url.query_pairs()
+        .map(|(name, value)| match name.as_ref() {
+            "key" => (name.into_owned(), REDACTED.to_string()),
+            _ => (name.into_owned(), value.into_owned())
+        })
+        .collect::<Vec<_>>();
+    redacted.query_pairs_mut().clear().extend_pairs(pairs);
+    redacted
+}
+
+impl Interaction {
+    fn into_response(self) -> Response {
+        let mut builder = http::Response::builder().status(self.status);
+        if let Some(etag) = self.etag {
+            builder = builder.header(ETAG, etag);
+        }
+        Response::from(builder.body(self.body).expect("recorded response must be valid"))
+    }
+}
+
+impl Cassette {
+    /// Recording always starts a new tape.
+    pub fn open(path: impl Into<PathBuf>, mode: CassetteMode) -> Cassette {
+        let path = path.into();
+        let tape = match mode {
+            CassetteMode::Record => Tape::default(),
+            CassetteMode::Replay => std::fs::read_to_string(&path)
+                .ok()
+                .and_then(|buf| serde_json::from_str::<Tape>(&buf).ok())
+                .unwrap_or_else(|| {
+                    warn!("cannot read cassette {}, nothing will be replayed.", path.display());
+                    Tape::default()
+                })
+        };
+        Self { path, mode, tape: Mutex::new(tape), cursor: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.mode == CassetteMode::Replay
+    }
+
+    /// Save the response, and give back an equivalent one.
+    pub async fn record(&self, url: &Url, res: Response) -> Result<Response, YoutubeApiError> {
+        let status = res.status().as_u16();
+        let etag = res.headers().get(ETAG)
+            .and_then(|value| value.to_str().ok())
+            .map(ToString::to_string);
+        let body = res.text().await?;
+        let interaction = Interaction { url: redact(url).to_string(), status, etag, body };
+
+        let mut tape = self.tape.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        tape.interactions.push(interaction.clone());
+        if let Err(reason) = self.persist(&tape) {
+            warn!("cannot write cassette: {}", reason);
+        }
+        Ok(interaction.into_response())
+    }
+
+    pub fn replay(&self, request: RequestBuilder) -> Result<Response, YoutubeApiError> {
+        let request = request.build()?;
+        let key = request_key(request.url());
+        let tape = self.tape.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let recorded = tape.interactions.iter()
+            .filter(|interaction| Url::parse(&interaction.url).map(|url| request_key(&url) == key).unwrap_or(false))
+            .collect::<Vec<_>>();
+        let mut cursor = self.cursor.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let index = cursor.entry(key.clone()).or_insert(0);
+        let interaction = recorded.get(*index)
+            .or_else(|| recorded.last())
+            .ok_or_else(|| YoutubeApiError::NotRecorded(key.clone()))?;
+        *index += 1;
+        Ok((*interaction).clone().into_response())
+    }
+
+    fn persist(&self, tape: &Tape) -> std::io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&self.path, serde_json::to_vec_pretty(tape)?)
+    }
+}
+
+#[cfg(test)]
+mod cassette_test {
+    use reqwest::{Client, StatusCode, Url};
+    use crate::entry::cassette::{Cassette, CassetteMode, request_key};
+
+    fn response(status: u16, body: &str) -> reqwest::Response {
+        reqwest::Response::from(hyper::http::Response::builder()
+            .status(status)
+            .header("etag", "recorded-etag")
+            .body(body.to_string())
+            .expect(""))
+    }
+
+    #[tokio::test]
+    async fn record_replay_test() {
+        let path = std::env::temp_dir().join(format!("salmon_cassette_test_{}.json", std::process::id()));
+        let url = Url::parse("https://www.googleapis.com/youtube/v3/videos?id=Xq3bQ0nTZMg&part=snippet&key=secret-key").expect("");
+
+        let recorder = Cassette::open(&path, CassetteMode::Record);
+        let res = recorder.record(&url, response(200, r#"{"etag": "1", "items": []}"#)).await.expect("");
+        assert_eq!(res.text().await.expect(""), r#"{"etag": "1", "items": []}"#);
+        recorder.record(&url, response(503, "unavailable")).await.expect("");
+        let written = std::fs::read_to_string(&path).expect("");
+        assert!(!written.contains("secret-key"));
+        assert!(written.contains("key=REDACTED"));
+
+        // replayed in order regardless of the host and the key, then the last one is repeated.
+        let player = Cassette::open(&path, CassetteMode::Replay);
+        let client = Client::new();
+        let request = || client.get("http://127.0.0.1:1/videos")
+            .query(&[("part", "snippet"), ("id", "Xq3bQ0nTZMg"), ("key", "another-key")]);
+        let first = player.replay(request()).expect("");
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(first.headers().get("etag").expect("").to_str().expect(""), "recorded-etag");
+        assert_eq!(player.replay(request()).expect("").status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(player.replay(request()).expect("").status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(player.replay(client.get("http://127.0.0.1:1/videos").query(&[("id", "other")])).is_err());
+
+        assert_eq!(request_key(&url), "videos?id=Xq3bQ0nTZMg&part=snippet");
+        let _ = std::fs::remove_file(&path);
+    }
+}
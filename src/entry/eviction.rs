@@ -0,0 +1,143 @@
+use std::sync::Arc;
+use chrono::{DateTime, Local};
+use once_cell::sync::OnceCell;
+use tracing::debug;
+use crate::entry::cache::Cache;
+use crate::entry::request::VideoInfo;
+use crate::ids::StringId;
+use crate::models::AffiliationEntry;
+
+/// How long a sent deletion is remembered, `TOMBSTONE_TTL_HOURS` (default: 168).
+pub fn get_tombstone_ttl() -> &'static chrono::Duration {
+    static TTL: OnceCell<chrono::Duration> = OnceCell::new();
+    TTL.get_or_init(|| {
+        dotenv::var("TOMBSTONE_TTL_HOURS")
+            .ok()
+            .and_then(|f| f.parse().ok())
+            .map(chrono::Duration::hours)
+            .unwrap_or_else(|| chrono::Duration::hours(168))
+    })
+}
+
+/// Videos kept in the cache of each affiliation, `VIDEO_CACHE_CAPACITY` (default: 1000).
+pub fn get_video_cache_capacity() -> &'static usize {
+    static CAPACITY: OnceCell<usize> = OnceCell::new();
+    CAPACITY.get_or_init(|| {
+        dotenv::var("VIDEO_CACHE_CAPACITY")
+            .ok()
+            .and_then(|f| f.parse().ok())
+            .map(|capacity: usize| capacity.max(1))
+            .unwrap_or(1000)
+    })
+}
+
+/// Deleted videos of the affiliation.
+pub(crate) fn tombstone_name(aff: &AffiliationEntry) -> String {
+    format!("video_tombstone_{}_cache", aff.as_ref_name())
+}
+
+/// Videos whose deletion was acknowledged by API Server, with the time of it.
+///
+/// Buried videos are neither cached nor sent again until the tombstone expires,
+/// so that each deletion is sent exactly once.
+pub struct Tombstones {
+    caching: Arc<dyn Cache<StringId<VideoInfo>, DateTime<Local>>>
+}
+
+impl Tombstones {
+    pub fn new(caching: Arc<dyn Cache<StringId<VideoInfo>, DateTime<Local>>>) -> Tombstones {
+        Self { caching }
+    }
+
+    pub async fn is_buried(&self, video: &StringId<VideoInfo>, ttl: chrono::Duration) -> bool {
+        self.caching.find_value(video).await
+            .map(|buried_at| Local::now() - buried_at < ttl)
+            .unwrap_or(false)
+    }
+
+    pub async fn bury(&self, videos: impl IntoIterator<Item = StringId<VideoInfo>>, at: DateTime<Local>) {
+        self.caching.put_all(videos.into_iter().map(|video| (video, at)).collect()).await;
+    }
+
+    /// Drop tombstones older than `ttl`, and return the videos of them.
+    pub async fn prune(&self, ttl: chrono::Duration) -> Vec<StringId<VideoInfo>> {
+        let now = Local::now();
+        let expired = self.caching.all_items().await.into_iter()
+            .filter(|(_, buried_at)| now - *buried_at >= ttl)
+            .map(|(video, _)| video)
+            .collect::<Vec<_>>();
+        if !expired.is_empty() {
+            self.caching.remove(&expired).await;
+            debug!("pruned {} tombstones.", expired.len());
+        }
+        expired
+    }
+}
+
+/// Videos to evict so that `capacity` are left.
+///
+/// The farthest from `now` by scheduled start are evicted first, that is, far future reservations
+/// which are discovered again later, and stale ones which never started. Videos without schedule go first.
+pub fn over_capacity(videos: &[VideoInfo], capacity: usize, now: DateTime<Local>) -> Vec<StringId<VideoInfo>> {
+    if videos.len() <= capacity {
+        return Vec::new()
+    }
+    let mut ordered = videos.iter()
+        .map(|video| {
+            let distance = video.as_ref_live_streaming_details().as_ref_scheduled_start_time_optional()
+                .map(|scheduled| (scheduled - now).num_seconds().abs());
+            (distance, video.as_ref_id())
+        })
+        .collect::<Vec<_>>();
+    ordered.sort_by(|(a, x), (b, y)| match (a, b) {
+        (None, None) => x.as_ref().cmp(y.as_ref()),
+        (None, Some(_)) => std::cmp::Ordering::Less,
+        (Some(_), None) => std::cmp::Ordering::Greater,
+        (Some(a), Some(b)) => b.cmp(a).then_with(|| x.as_ref().cmp(y.as_ref()))
+    });
+    ordered.into_iter()
+        .take(videos.len() - capacity)
+        .map(|(_, id)| id.to_owned())
+        .collect()
+}
+
+#[cfg(test)]
+mod eviction_test {
+    use std::sync::Arc;
+    use chrono::{Duration, Local, TimeZone};
+    use serde_json::json;
+    use crate::entry::cache::JsonCache;
+    use crate::entry::eviction::{over_capacity, Tombstones};
+    use crate::entry::mock::video;
+    use crate::ids::StringId;
+
+    #[tokio::test]
+    async fn eviction_test() {
+        let now = Local.with_ymd_and_hms(2099, 1, 2, 12, 0, 0).unwrap();
+        let videos = vec![
+            video("NeXtHoUr001", json!({ "scheduledStartTime": "2099-01-02T04:00:00Z" })),
+            video("NeXtMoNtH01", json!({ "scheduledStartTime": "2099-02-02T03:00:00Z" })),
+            video("StAlE000001", json!({ "scheduledStartTime": "2098-12-01T03:00:00Z" })),
+            video("UnKnOwN0001", json!({}))
+        ];
+        assert!(over_capacity(&videos, 4, now).is_empty());
+        let evicted = over_capacity(&videos, 1, now).into_iter().map(|id| id.as_ref().to_string()).collect::<Vec<_>>();
+        assert_eq!(evicted, vec!["UnKnOwN0001", "StAlE000001", "NeXtMoNtH01"]);
+
+        // buried until the tombstone expires.
+        let tombstone = std::env::temp_dir().join(format!("salmon_tombstone_test_{}.json", std::process::id())).to_string_lossy().to_string();
+        let _ = std::fs::remove_file(&tombstone);
+        let tombstones = Tombstones::new(Arc::new(JsonCache::load(tombstone.as_str())));
+        tombstones.bury([StringId::new("StAlE000001")], Local::now()).await;
+        tombstones.bury([StringId::new("EnDeD000001")], Local::now() - Duration::hours(2)).await;
+        assert!(tombstones.is_buried(&StringId::new("StAlE000001"), Duration::hours(1)).await);
+        assert!(!tombstones.is_buried(&StringId::new("EnDeD000001"), Duration::hours(1)).await);
+        assert!(!tombstones.is_buried(&StringId::new("NeXtHoUr001"), Duration::hours(1)).await);
+        assert_eq!(tombstones.prune(Duration::hours(1)).await, vec![StringId::new("EnDeD000001")]);
+        let tombstones = Tombstones::new(Arc::new(JsonCache::load(tombstone.as_str())));
+        assert!(tombstones.is_buried(&StringId::new("StAlE000001"), Duration::hours(1)).await);
+        assert!(tombstones.prune(Duration::hours(1)).await.is_empty());
+
+        let _ = std::fs::remove_file(&tombstone);
+    }
+}
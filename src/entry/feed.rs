@@ -0,0 +1,182 @@
+use anyhow::{Context, Result};
+use once_cell::sync::OnceCell;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use reqwest::StatusCode;
+use crate::entry::request::VideoInfo;
+use crate::entry::scheduler::get_scheduler;
+use crate::ids::StringId;
+use crate::models::Channel;
+
+const DEFAULT_FEED_BASE_URL: &str = "https://www.youtube.com/feeds/videos.xml";
+
+/// Base url of channel Atom feeds, configurable by `FEED_BASE_URL`.
+///
+/// `{channel_id}` in the url is replaced with the channel id,
+/// otherwise `?channel_id=` is appended.
+/// e.g. `FEED_BASE_URL=http://127.0.0.1:8000/{channel_id}.xml` serves `.test/feeds` locally.
+fn get_feed_base_url() -> &'static str {
+    static BASE: OnceCell<String> = OnceCell::new();
+    BASE.get_or_init(|| {
+        dotenv::var("FEED_BASE_URL")
+            .unwrap_or_else(|_| DEFAULT_FEED_BASE_URL.to_string())
+    })
+}
+
+pub(super) fn feed_url(base: &str, channel: &StringId<Channel>) -> String {
+    if base.contains("{channel_id}") {
+        base.replace("{channel_id}", channel.as_ref())
+    } else {
+        format!("{}?channel_id={}", base, channel.as_ref())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum FeedError {
+    #[error("failed get feed.")]
+    HttpGet,
+    #[error("unexpected status code: {}", .0)]
+    UnexpectedStatus(StatusCode),
+    #[error("cannot parse feed.")]
+    Parse,
+}
+
+/// An `<entry>` of YouTube Atom feed.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct FeedEntry {
+    video_id: StringId<VideoInfo>,
+    channel_id: StringId<Channel>,
+    title: String
+}
+
+impl FeedEntry {
+    pub fn as_ref_video_id(&self) -> &StringId<VideoInfo> {
+        &self.video_id
+    }
+
+    pub fn as_ref_channel_id(&self) -> &StringId<Channel> {
+        &self.channel_id
+    }
+
+    pub fn as_ref_title(&self) -> &str {
+        &self.title
+    }
+}
+
+/// Fetch `feeds/videos.xml` of the channel. Costs no quota.
+pub(super) async fn fetch_feed(client: &reqwest::Client, channel: &StringId<Channel>) -> Result<Vec<FeedEntry>> {
+    fetch_feed_from(client, get_feed_base_url(), channel).await
+}
+
+async fn fetch_feed_from(client: &reqwest::Client, base: &str, channel: &StringId<Channel>) -> Result<Vec<FeedEntry>> {
+    let permit = get_scheduler().acquire().await;
+    let res = client.get(feed_url(base, channel))
+        .send().await
+        .context(FeedError::HttpGet)?;
+    drop(permit);
+    if res.status() != StatusCode::OK {
+        return Err(FeedError::UnexpectedStatus(res.status()).into())
+    }
+    let body = res.text().await
+        .context(FeedError::HttpGet)?;
+    parse_feed(&body)
+}
+
+/// Collect entries of Atom feed, used for both channel feeds and WebSub notifications.
+pub fn parse_feed(xml: &str) -> Result<Vec<FeedEntry>> {
+    #[derive(Default)]
+    struct Partial {
+        video_id: Option<String>,
+        channel_id: Option<String>,
+        title: Option<String>
+    }
+
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+
+    let mut entries = Vec::new();
+    let mut current: Option<Partial> = None;
+    let mut element: Vec<u8> = Vec::new();
+    loop {
+        match reader.read_event().context(FeedError::Parse)? {
+            Event::Start(tag) if tag.name().as_ref() == b"entry" => current = Some(Partial::default()),
+            Event::Start(tag) => element = tag.name().as_ref().to_vec(),
+            Event::Text(text) => {
+                let partial = match current.as_mut() {
+                    Some(partial) => partial,
+                    None => continue
+                };
+                let text = text.unescape().context(FeedError::Parse)?.into_owned();
+                match element.as_slice() {
+                    b"yt:videoId" => partial.video_id = Some(text),
+                    b"yt:channelId" => partial.channel_id = Some(text),
+                    b"title" => partial.title = Some(text),
+                    _ => ()
+                }
+            },
+            Event::End(tag) if tag.name().as_ref() == b"entry" => {
+                if let Some(Partial { video_id: Some(video_id), channel_id: Some(channel_id), title }) = current.take() {
+                    entries.push(FeedEntry {
+                        video_id: StringId::new(video_id),
+                        channel_id: StringId::new(channel_id),
+                        title: title.unwrap_or_default()
+                    });
+                }
+            },
+            Event::End(_) => element.clear(),
+            Event::Eof => break,
+            _ => ()
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod feed_test {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use crate::entry::feed::{feed_url, fetch_feed_from, parse_feed};
+    use crate::ids::StringId;
+    use crate::models::Channel;
+
+    const CHANNEL: &str = "UCvaTdHTWBGv3MKj3KVqJVCw";
+
+    #[test]
+    fn parse_test() {
+        let xml = std::fs::read_to_string(format!(".test/feeds/{}.xml", CHANNEL)).expect("");
+        let entries = parse_feed(&xml).expect("");
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].as_ref_video_id().as_ref(), "Xq3bQ0nTZMg");
+        assert_eq!(entries[0].as_ref_channel_id().as_ref(), CHANNEL);
+        assert_eq!(entries[0].as_ref_title(), "【雑談】おはようおかゆ & 告知！【猫又おかゆ/ホロライブ】");
+        assert_eq!(entries[1].as_ref_video_id().as_ref(), "7kAa2Yh3z_Q");
+    }
+
+    #[test]
+    fn feed_url_test() {
+        let channel = StringId::<Channel>::new(CHANNEL);
+        assert_eq!(feed_url("https://www.youtube.com/feeds/videos.xml", &channel),
+                   format!("https://www.youtube.com/feeds/videos.xml?channel_id={}", CHANNEL));
+        assert_eq!(feed_url("http://127.0.0.1:8000/{channel_id}.xml", &channel),
+                   format!("http://127.0.0.1:8000/{}.xml", CHANNEL));
+    }
+
+    #[tokio::test]
+    async fn fetch_local_fixture_test() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("");
+        let address = listener.local_addr().expect("");
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.expect("");
+            let mut buf = [0u8; 1024];
+            let _ = socket.read(&mut buf).await;
+            let body = std::fs::read(format!(".test/feeds/{}.xml", CHANNEL)).expect("");
+            let header = format!("HTTP/1.1 200 OK\r\nContent-Type: application/atom+xml\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+            socket.write_all(header.as_bytes()).await.expect("");
+            socket.write_all(&body).await.expect("");
+        });
+
+        let base = format!("http://{}/{{channel_id}}.xml", address);
+        let entries = fetch_feed_from(&reqwest::Client::new(), &base, &StringId::new(CHANNEL)).await.expect("");
+        assert_eq!(entries.len(), 2);
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
+use chrono_tz::US::Pacific;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use tracing::{error, info, warn};
+use crate::entry::{cache_path, write_blocking};
+use crate::entry::quota::quota_day;
+
+/// Keys are read from `API_KEYS` (comma separated), or from `API_KEY` when not set.
+pub fn get_api_key_pool() -> &'static ApiKeyPool {
+    static POOL: OnceCell<ApiKeyPool> = OnceCell::new();
+    POOL.get_or_init(|| {
+        let keys = dotenv::var("API_KEYS")
+            .or_else(|_| dotenv::var("API_KEY"))
+            .map(|keys| keys.split(',')
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+                .map(ToString::to_string)
+                .collect::<Vec<_>>())
+            .unwrap_or_default();
+        ApiKeyPool::load(cache_path("api_keys.json"), keys)
+    })
+}
+
+/// Start of the next quota day, midnight in Pacific Time.
+pub fn next_quota_reset() -> DateTime<Local> {
+    let midnight = quota_day().succ_opt()
+        .and_then(|day| day.and_hms_opt(0, 0, 0))
+        .expect("date out of range");
+    Pacific.from_local_datetime(&midnight)
+        .earliest()
+        .map(|reset| reset.with_timezone(&Local))
+        .expect("midnight must exist in Pacific Time")
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum KeyPoolError {
+    #[error("no API key is set. set API_KEYS or API_KEY.")]
+    Empty,
+    #[error("all {} API keys have exhausted their quota until {}.", .count, .reset)]
+    AllExhausted { count: usize, reset: DateTime<Local> },
+}
+
+/// Rotates API keys, and tracks quota units spent with each key per Pacific-time day.
+///
+/// A key that got `quotaExceeded` is cooled down until the next daily reset.
+/// Keys are identified by a fingerprint in logs and in `api_keys.json` of the cache directory,
+/// so that the raw key is never written out.
+/// The file is written on a blocking thread when a key is cooled down or the day is reset,
+/// units spent are written along with them.
+#[derive(Debug)]
+pub struct ApiKeyPool {
+    keys: Vec<String>,
+    path: PathBuf,
+    cursor: AtomicUsize,
+    state: Arc<Mutex<PoolState>>,
+    writer: Arc<Mutex<()>>
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct PoolState {
+    day: Option<NaiveDate>,
+    #[serde(default)]
+    keys: BTreeMap<String, KeyState>
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct KeyState {
+    used: u64,
+    exhausted: bool
+}
+
+impl PoolState {
+    /// Whether the day is reset.
+    fn roll_over(&mut self, today: NaiveDate) -> bool {
+        if self.day == Some(today) {
+            return false
+        }
+        self.day = Some(today);
+        self.keys.clear();
+        true
+    }
+}
+
+pub fn fingerprint(key: &str) -> String {
+    hex::encode(&Sha1::digest(key.as_bytes())[..6])
+}
+
+impl ApiKeyPool {
+    pub fn load(path: impl Into<PathBuf>, keys: Vec<String>) -> ApiKeyPool {
+        let path = path.into();
+        let state = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|buf| serde_json::from_str::<PoolState>(&buf).ok())
+            .unwrap_or_default();
+        Self { keys, path, cursor: AtomicUsize::new(0), state: Arc::new(Mutex::new(state)), writer: Arc::new(Mutex::new(())) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Whether any key is not cooled down.
+    pub fn is_available(&self) -> bool {
+        self.acquire().is_ok()
+    }
+
+    /// Next key in round-robin order, skipping exhausted keys.
+    pub fn acquire(&self) -> Result<&str, KeyPoolError> {
+        if self.keys.is_empty() {
+            return Err(KeyPoolError::Empty)
+        }
+        let mut state = self.state.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        state.roll_over(quota_day());
+        let start = self.cursor.fetch_add(1, Ordering::Relaxed);
+        (0..self.keys.len())
+            .map(|offset| &self.keys[(start + offset) % self.keys.len()])
+            .find(|key| !state.keys.get(&fingerprint(key)).map(|key| key.exhausted).unwrap_or(false))
+            .map(String::as_str)
+            .ok_or(KeyPoolError::AllExhausted { count: self.keys.len(), reset: next_quota_reset() })
+    }
+
+    pub fn record(&self, key: &str, units: u64) {
+        self.update(key, |state| state.used += units);
+    }
+
+    /// Cool down the key until the next daily reset.
+    pub fn exhaust(&self, key: &str) {
+        warn!(key = %fingerprint(key), "API key exhausted its quota, cooled down until {}.", next_quota_reset());
+        self.update(key, |state| state.exhausted = true);
+        if !self.is_available() {
+            error!("all {} API keys have exhausted their quota.", self.keys.len());
+        }
+    }
+
+    pub fn report(&self) {
+        let mut state = self.state.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        state.roll_over(quota_day());
+        for key in self.keys.iter() {
+            let fingerprint = fingerprint(key);
+            let usage = state.keys.get(&fingerprint).cloned().unwrap_or_default();
+            info!(key = %fingerprint, key_used = usage.used, key_exhausted = usage.exhausted,
+                "key {}: {} units today{}", fingerprint, usage.used, if usage.exhausted { " (exhausted)" } else { "" });
+        }
+    }
+
+    fn update(&self, key: &str, f: impl FnOnce(&mut KeyState)) {
+        let mut state = self.state.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let rolled = state.roll_over(quota_day());
+        let usage = state.keys.entry(fingerprint(key)).or_default();
+        let exhausted = usage.exhausted;
+        f(usage);
+        let changed = rolled || usage.exhausted != exhausted;
+        drop(state);
+        if changed {
+            self.persist();
+        }
+    }
+
+    fn persist(&self) {
+        let (path, state, writer) = (self.path.clone(), Arc::clone(&self.state), Arc::clone(&self.writer));
+        write_blocking(move || {
+            // writes are serialized and snapshot after taking turn, so that the last write is the newest.
+            let _turn = writer.lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let snapshot = state.lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .clone();
+            if let Err(reason) = write_pool(&path, &snapshot) {
+                warn!("cannot persist API key usage: {}", reason);
+            }
+        });
+    }
+}
+
+fn write_pool(path: &Path, state: &PoolState) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, serde_json::to_vec(state)?)
+}
+
+#[cfg(test)]
+mod keys_test {
+    use crate::entry::keys::{ApiKeyPool, fingerprint, KeyPoolError};
+
+    #[test]
+    fn rotation_test() {
+        let path = std::env::temp_dir().join(format!("salmon_keys_test_{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let keys = vec!["first-key".to_string(), "second-key".to_string()];
+        let pool = ApiKeyPool::load(&path, keys.clone());
+
+        assert_eq!(pool.acquire().expect(""), "first-key");
+        assert_eq!(pool.acquire().expect(""), "second-key");
+
+        pool.exhaust("first-key");
+        pool.record("second-key", 100);
+        // spent units alone do not change the state of a key.
+        assert!(!std::fs::read_to_string(&path).expect("").contains(&fingerprint("second-key")));
+        assert_eq!(pool.acquire().expect(""), "second-key");
+        assert_eq!(pool.acquire().expect(""), "second-key");
+
+        // cool down is carried over to next run, and raw keys are never written.
+        let reloaded = ApiKeyPool::load(&path, keys);
+        assert_eq!(reloaded.acquire().expect(""), "second-key");
+        let persisted = std::fs::read_to_string(&path).expect("");
+        assert!(!persisted.contains("first-key"));
+        assert!(persisted.contains(&fingerprint("first-key")));
+
+        reloaded.exhaust("second-key");
+        assert!(matches!(reloaded.acquire(), Err(KeyPoolError::AllExhausted { count: 2, .. })));
+        assert!(matches!(ApiKeyPool::load(&path, vec![]).acquire(), Err(KeyPoolError::Empty)));
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,389 @@
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use chrono::{DateTime, Local};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tracing::info;
+use crate::entry::cache::{Cache, open_cache};
+use crate::entry::request::{LiveStreamingDetails, VideoInfo};
+use crate::ids::StringId;
+
+/// Last known states of videos, `lifecycle` cache.
+pub fn get_lifecycle_store() -> &'static LifecycleStore {
+    static STORE: OnceCell<LifecycleStore> = OnceCell::new();
+    STORE.get_or_init(|| LifecycleStore::new(open_cache("lifecycle")))
+}
+
+/// How long a tracked video may be missing from `videos` responses before it is cancelled,
+/// `CANCEL_GRACE_MINUTES` (default: 30).
+pub fn get_cancel_grace() -> &'static chrono::Duration {
+    static GRACE: OnceCell<chrono::Duration> = OnceCell::new();
+    GRACE.get_or_init(|| {
+        dotenv::var("CANCEL_GRACE_MINUTES")
+            .ok()
+            .and_then(|f| f.parse().ok())
+            .map(chrono::Duration::minutes)
+            .unwrap_or_else(|| chrono::Duration::minutes(30))
+    })
+}
+
+fn get_event_sender() -> &'static broadcast::Sender<LiveEvent> {
+    static SENDER: OnceCell<broadcast::Sender<LiveEvent>> = OnceCell::new();
+    SENDER.get_or_init(|| broadcast::channel(256).0)
+}
+
+/// Receive transitions observed after this call.
+pub fn subscribe() -> broadcast::Receiver<LiveEvent> {
+    get_event_sender().subscribe()
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum LiveState {
+    Scheduled,
+    Live,
+    Ended,
+    /// Disappeared before it ended, never derived from the details.
+    Cancelled,
+    /// Not a live stream, or nothing is known.
+    Unknown
+}
+
+impl From<&LiveStreamingDetails> for LiveState {
+    fn from(details: &LiveStreamingDetails) -> Self {
+        if details.as_ref_actual_end_time_optional().is_some() {
+            LiveState::Ended
+        } else if details.as_ref_actual_start_time_optional().is_some() {
+            LiveState::Live
+        } else if details.as_ref_scheduled_start_time_optional().is_some() {
+            LiveState::Scheduled
+        } else {
+            LiveState::Unknown
+        }
+    }
+}
+
+impl Display for LiveState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let state = match self {
+            LiveState::Scheduled => "scheduled",
+            LiveState::Live => "live",
+            LiveState::Ended => "ended",
+            LiveState::Cancelled => "cancelled",
+            LiveState::Unknown => "unknown"
+        };
+        f.write_str(state)
+    }
+}
+
+/// Live now, or scheduled start has passed. The cached copy of such video is likely stale.
+pub fn may_be_live(video: &VideoInfo) -> bool {
+    match video.live_state() {
+        LiveState::Live => true,
+        LiveState::Scheduled => video.as_ref_live_streaming_details().as_ref_scheduled_start_time_optional()
+            .map(|at| at <= Local::now())
+            .unwrap_or(false),
+        _ => false
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Transition {
+    Scheduled { at: DateTime<Local> },
+    Started { at: Option<DateTime<Local>> },
+    Ended { at: Option<DateTime<Local>> },
+    Rescheduled { from: DateTime<Local>, to: DateTime<Local> },
+    Cancelled
+}
+
+impl Display for Transition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Transition::Scheduled { at } => write!(f, "scheduled at {}", at),
+            Transition::Started { .. } => write!(f, "started"),
+            Transition::Ended { .. } => write!(f, "ended"),
+            Transition::Rescheduled { from, to } => write!(f, "rescheduled, {}", ScheduleChange::new(*from, *to, Local::now())),
+            Transition::Cancelled => write!(f, "cancelled")
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LiveEvent {
+    video: StringId<VideoInfo>,
+    transition: Transition
+}
+
+impl LiveEvent {
+    pub fn as_ref_video(&self) -> &StringId<VideoInfo> {
+        &self.video
+    }
+
+    pub fn as_ref_transition(&self) -> &Transition {
+        &self.transition
+    }
+}
+
+/// A move of the scheduled start time.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleChange {
+    from: DateTime<Local>,
+    to: DateTime<Local>,
+    changed_at: DateTime<Local>
+}
+
+impl ScheduleChange {
+    pub fn new(from: DateTime<Local>, to: DateTime<Local>, changed_at: DateTime<Local>) -> ScheduleChange {
+        Self { from, to, changed_at }
+    }
+
+    pub fn as_ref_from(&self) -> &DateTime<Local> {
+        &self.from
+    }
+
+    pub fn as_ref_to(&self) -> &DateTime<Local> {
+        &self.to
+    }
+
+    pub fn as_ref_changed_at(&self) -> &DateTime<Local> {
+        &self.changed_at
+    }
+
+    /// Positive when delayed, negative when advanced.
+    pub fn delay(&self) -> chrono::Duration {
+        self.to - self.from
+    }
+}
+
+impl Display for ScheduleChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let minutes = self.delay().num_minutes();
+        write!(f, "moved from {} to {} ({}{}min)", self.from.format("%m/%d %H:%M"), self.to.format("%m/%d %H:%M"),
+            if minutes >= 0 { "+" } else { "" }, minutes)
+    }
+}
+
+/// Persisted state of a video.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LiveRecord {
+    state: LiveState,
+    #[serde(default)]
+    scheduled_start_time: Option<DateTime<Local>>,
+    changed_at: DateTime<Local>,
+    /// Every move of the schedule, oldest first.
+    #[serde(default)]
+    history: Vec<ScheduleChange>,
+    /// First time the video was not returned by `videos`.
+    #[serde(default)]
+    missing_since: Option<DateTime<Local>>
+}
+
+impl LiveRecord {
+    pub fn of(video: &VideoInfo) -> LiveRecord {
+        Self {
+            state: video.live_state(),
+            scheduled_start_time: *video.as_ref_live_streaming_details().as_ref_scheduled_start_time_optional(),
+            changed_at: Local::now(),
+            history: Vec::new(),
+            missing_since: None
+        }
+    }
+
+    pub fn as_ref_history(&self) -> &[ScheduleChange] {
+        &self.history
+    }
+
+    #[cfg(test)]
+    pub fn as_ref_state(&self) -> &LiveState {
+        &self.state
+    }
+}
+
+/// Transitions from `prev` to `next` of the same video.
+fn transitions(prev: Option<&LiveRecord>, next: &LiveRecord, details: &LiveStreamingDetails) -> Vec<Transition> {
+    let entered = match (prev.map(|prev| prev.state), next.state) {
+        (Some(prev), next) if prev == next => None,
+        (_, LiveState::Scheduled) => next.scheduled_start_time.map(|at| Transition::Scheduled { at }),
+        (_, LiveState::Live) => Some(Transition::Started { at: *details.as_ref_actual_start_time_optional() }),
+        (_, LiveState::Ended) => Some(Transition::Ended { at: *details.as_ref_actual_end_time_optional() }),
+        (_, LiveState::Cancelled) => Some(Transition::Cancelled),
+        (_, LiveState::Unknown) => None
+    };
+    let moved = match (prev, next.state) {
+        (Some(prev), LiveState::Scheduled) if prev.state == LiveState::Scheduled => match (prev.scheduled_start_time, next.scheduled_start_time) {
+            (Some(from), Some(to)) if from != to => Some(Transition::Rescheduled { from, to }),
+            _ => None
+        },
+        _ => None
+    };
+    entered.into_iter().chain(moved).collect()
+}
+
+/// Tracks [`LiveState`] of each video, and emits [`LiveEvent`] on every transition.
+pub struct LifecycleStore {
+    caching: Arc<dyn Cache<StringId<VideoInfo>, LiveRecord>>
+}
+
+impl LifecycleStore {
+    pub fn new(caching: Arc<dyn Cache<StringId<VideoInfo>, LiveRecord>>) -> LifecycleStore {
+        Self { caching }
+    }
+
+    /// Store in a JSON file at `path`.
+    #[cfg(test)]
+    pub fn load(path: impl Into<String>) -> LifecycleStore {
+        Self::new(Arc::new(crate::entry::cache::JsonCache::load(path)))
+    }
+
+    pub async fn find(&self, video: &StringId<VideoInfo>) -> Option<LiveRecord> {
+        self.caching.find_value(video).await
+    }
+
+    /// Compare fetched details with the last known state, then save and emit the transitions.
+    pub async fn observe(&self, video: &VideoInfo) -> Vec<Transition> {
+        let prev = self.find(video.as_ref_id()).await;
+        let mut next = LiveRecord::of(video);
+        let found = transitions(prev.as_ref(), &next, video.as_ref_live_streaming_details());
+        next.history = prev.as_ref().map(|prev| prev.history.clone()).unwrap_or_default();
+        next.history.extend(found.iter().filter_map(|transition| match transition {
+            Transition::Rescheduled { from, to } => Some(ScheduleChange::new(*from, *to, next.changed_at)),
+            _ => None
+        }));
+        if prev.as_ref().map(|prev| prev.state != next.state || prev.scheduled_start_time != next.scheduled_start_time || prev.missing_since.is_some()).unwrap_or(true) {
+            self.caching.put(video.as_ref_id().to_owned(), next).await;
+        }
+        self.emit(video.as_ref_id(), &found);
+        found
+    }
+
+    /// Record that the video was not returned, and whether it is cancelled after `grace` since it went missing.
+    pub async fn missing(&self, video: &StringId<VideoInfo>, grace: chrono::Duration) -> bool {
+        let prev = self.find(video).await;
+        match prev.as_ref().map(|prev| prev.state) {
+            Some(LiveState::Cancelled) => return true,
+            Some(LiveState::Ended) => return false,
+            _ => ()
+        }
+        let now = Local::now();
+        let since = prev.as_ref().and_then(|prev| prev.missing_since).unwrap_or(now);
+        if now - since >= grace {
+            self.cancel(video).await;
+            return true
+        }
+        if prev.as_ref().and_then(|prev| prev.missing_since).is_none() {
+            info!(video = %video, "{} is missing, cancelled after {}min unless it returns.", video.as_ref(), grace.num_minutes());
+            let mut next = prev.unwrap_or_else(|| LiveRecord {
+                state: LiveState::Unknown,
+                scheduled_start_time: None,
+                changed_at: now,
+                history: Vec::new(),
+                missing_since: None
+            });
+            next.missing_since = Some(now);
+            self.caching.put(video.to_owned(), next).await;
+        }
+        false
+    }
+
+    /// Drop the records of videos which are no longer tracked.
+    pub async fn forget(&self, videos: &[StringId<VideoInfo>]) {
+        if !videos.is_empty() {
+            self.caching.remove(videos).await;
+        }
+    }
+
+    /// Mark the video as cancelled, unless it has ended already.
+    pub async fn cancel(&self, video: &StringId<VideoInfo>) -> Vec<Transition> {
+        let prev = self.find(video).await;
+        if prev.as_ref().map(|prev| matches!(prev.state, LiveState::Ended | LiveState::Cancelled)).unwrap_or(false) {
+            return Vec::new()
+        }
+        let next = LiveRecord {
+            state: LiveState::Cancelled,
+            scheduled_start_time: prev.as_ref().and_then(|prev| prev.scheduled_start_time),
+            changed_at: Local::now(),
+            history: prev.as_ref().map(|prev| prev.history.clone()).unwrap_or_default(),
+            missing_since: prev.and_then(|prev| prev.missing_since)
+        };
+        self.caching.put(video.to_owned(), next).await;
+        let found = vec![Transition::Cancelled];
+        self.emit(video, &found);
+        found
+    }
+
+    fn emit(&self, video: &StringId<VideoInfo>, found: &[Transition]) {
+        for transition in found {
+            // no receiver is not an error.
+            let _ = get_event_sender().send(LiveEvent { video: video.to_owned(), transition: transition.clone() });
+        }
+    }
+}
+
+#[cfg(test)]
+mod lifecycle_test {
+    use serde_json::json;
+    use crate::entry::lifecycle::{LifecycleStore, LiveState, subscribe, Transition};
+    use crate::entry::mock;
+    use crate::entry::request::VideoInfo;
+    use crate::entry::transport::Applier;
+    use crate::entry::transport::salmon::Live;
+    use crate::ids::StringId;
+
+    fn video(details: serde_json::Value) -> VideoInfo {
+        mock::video("Xq3bQ0nTZMg", details)
+    }
+
+    #[tokio::test]
+    async fn transition_test() {
+        let path = std::env::temp_dir().join(format!("salmon_lifecycle_test_{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let store = LifecycleStore::load(path.to_string_lossy());
+        let mut events = subscribe();
+
+        let scheduled = video(json!({ "scheduledStartTime": "2099-01-02T12:00:00Z" }));
+        assert_eq!(scheduled.live_state(), LiveState::Scheduled);
+        assert!(!scheduled.is_live_finished());
+        assert!(matches!(store.observe(&scheduled).await[..], [Transition::Scheduled { .. }]));
+        assert!(store.observe(&scheduled).await.is_empty());
+
+        let moved = video(json!({ "scheduledStartTime": "2099-01-02T13:00:00Z" }));
+        assert!(matches!(store.observe(&moved).await[..], [Transition::Rescheduled { .. }]));
+        let advanced = video(json!({ "scheduledStartTime": "2099-01-02T12:30:00Z" }));
+        store.observe(&advanced).await;
+        let record = store.find(advanced.as_ref_id()).await.expect("");
+        let delays = record.as_ref_history().iter().map(|change| change.delay().num_minutes()).collect::<Vec<_>>();
+        assert_eq!(delays, vec![60, -30]);
+        let live = Live::from(advanced).apply(&record);
+        assert_eq!(live.reschedules.len(), 2);
+        assert_eq!(live.reschedules[1].delay_seconds, -1800);
+
+        // live after the schedule is not finished.
+        let live = video(json!({ "scheduledStartTime": "2020-01-02T13:00:00Z", "actualStartTime": "2020-01-02T13:01:00Z" }));
+        assert!(!live.is_live_finished());
+        assert!(matches!(store.observe(&live).await[..], [Transition::Started { at: Some(_) }]));
+
+        // persisted.
+        let store = LifecycleStore::load(path.to_string_lossy());
+        let ended = video(json!({ "actualStartTime": "2020-01-02T13:01:00Z", "actualEndTime": "2020-01-02T15:00:00Z" }));
+        assert!(ended.is_live_finished());
+        assert!(matches!(store.observe(&ended).await[..], [Transition::Ended { .. }]));
+        assert!(store.cancel(ended.as_ref_id()).await.is_empty());
+        assert!(!store.missing(ended.as_ref_id(), chrono::Duration::zero()).await);
+
+        // cancelled after the grace period, unless it returns.
+        let reserved = StringId::new("DeLeTeD0001");
+        assert!(!store.missing(&reserved, chrono::Duration::hours(1)).await);
+        assert!(store.find(&reserved).await.expect("").missing_since.is_some());
+        assert!(store.missing(&reserved, chrono::Duration::zero()).await);
+        assert_eq!(store.find(&reserved).await.expect("").as_ref_state(), &LiveState::Cancelled);
+        assert!(store.missing(&reserved, chrono::Duration::hours(1)).await);
+        // forgotten once evicted or the tombstone is pruned.
+        store.forget(std::slice::from_ref(&reserved)).await;
+        assert!(store.find(&reserved).await.is_none());
+
+        assert!(events.try_recv().is_ok());
+        assert!(video(json!({})).live_state() == LiveState::Unknown && !video(json!({})).is_live_finished());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
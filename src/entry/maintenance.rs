@@ -0,0 +1,135 @@
+use chrono::{DateTime, Local};
+use crate::cli::CacheCommand;
+use crate::entry::{load_affiliations, video_cache_name};
+use crate::entry::cache::{get_cache_backend, open_cache};
+use crate::entry::eviction::tombstone_name;
+use crate::entry::lifecycle::{get_lifecycle_store, LiveState};
+use crate::entry::request::{cached_etags, purge_etags, VideoInfo};
+use crate::ids::StringId;
+use crate::models::AffiliationEntry;
+
+pub(super) async fn run(command: CacheCommand) -> anyhow::Result<()> {
+    match command {
+        CacheCommand::List { affiliation } => list(affiliation.as_deref()).await,
+        CacheCommand::Show { id } => show(&id).await,
+        CacheCommand::Purge { affiliation, etags } => {
+            if let Some(affiliation) = affiliation {
+                let purged = purge_affiliation(&affiliation).await?;
+                eprintln!("purged {} videos of {}.", purged, affiliation);
+            }
+            if etags {
+                eprintln!("purged {} etags.", purge_etags().await);
+            }
+            Ok(())
+        },
+        CacheCommand::Stats => stats().await
+    }
+}
+
+/// Every affiliation in the config, or only the one named `only`.
+fn affiliations(only: Option<&str>) -> anyhow::Result<Vec<AffiliationEntry>> {
+    let all = load_affiliations()?;
+    match only {
+        None => Ok(all.into_iter().collect()),
+        Some(name) => {
+            let found = all.into_iter()
+                .filter(|aff| aff.as_ref_name() == name)
+                .collect::<Vec<_>>();
+            anyhow::ensure!(!found.is_empty(), "unknown affiliation: {}", name);
+            Ok(found)
+        }
+    }
+}
+
+async fn cached_videos(aff: &AffiliationEntry) -> Vec<VideoInfo> {
+    open_cache::<StringId<VideoInfo>, VideoInfo>(&video_cache_name(aff)).all_items().await.into_iter()
+        .map(|(_, video)| video)
+        .collect()
+}
+
+/// `affiliation  video id  state  scheduled start  title` per line, in order of the schedule.
+async fn list(affiliation: Option<&str>) -> anyhow::Result<()> {
+    for aff in affiliations(affiliation)? {
+        let mut videos = cached_videos(&aff).await;
+        videos.sort_by_key(|video| *video.as_ref_live_streaming_details().as_ref_scheduled_start_time_optional());
+        for video in videos {
+            let scheduled = video.as_ref_live_streaming_details().as_ref_scheduled_start_time_optional()
+                .map(|scheduled| scheduled.to_rfc3339())
+                .unwrap_or_else(|| String::from("-"));
+            println!("{}\t{}\t{}\t{}\t{}", aff.as_ref_name(), video.as_ref_id(), video.live_state(), scheduled, video.as_ref_title());
+        }
+    }
+    Ok(())
+}
+
+/// Cached video, state, tombstone and etags of the id.
+///
+/// Etags of `videos` batches are keyed by the scope and the joined ids of the batch, so a batch including the id is shown.
+async fn show(id: &str) -> anyhow::Result<()> {
+    let video = StringId::<VideoInfo>::new(id);
+    let mut found = false;
+    for aff in affiliations(None)? {
+        if let Some(cached) = open_cache::<StringId<VideoInfo>, VideoInfo>(&video_cache_name(&aff)).find_value(&video).await {
+            println!("video in {}:\n{}", aff.as_ref_name(), serde_json::to_string_pretty(&cached)?);
+            found = true;
+        }
+        if let Some(buried_at) = open_cache::<StringId<VideoInfo>, DateTime<Local>>(&tombstone_name(&aff)).find_value(&video).await {
+            println!("deletion sent at {} in {}", buried_at.to_rfc3339(), aff.as_ref_name());
+            found = true;
+        }
+    }
+    if let Some(record) = get_lifecycle_store().find(&video).await {
+        println!("lifecycle:\n{}", serde_json::to_string_pretty(&record)?);
+        found = true;
+    }
+    for (name, key, etag) in cached_etags().await {
+        let ids = key.split_once(':').map(|(_, ids)| ids).unwrap_or(&key);
+        if ids.split(',').any(|part| part == id) {
+            println!("{}\t{}\t{}", name, key, etag);
+            found = true;
+        }
+    }
+    if !found {
+        eprintln!("nothing is cached for {}.", id);
+    }
+    Ok(())
+}
+
+/// Remove cached videos and tombstones of the affiliation, and return how many videos were removed.
+pub(super) async fn purge_affiliation(affiliation: &str) -> anyhow::Result<usize> {
+    let mut purged = 0;
+    for aff in affiliations(Some(affiliation))? {
+        let videos = open_cache::<StringId<VideoInfo>, VideoInfo>(&video_cache_name(&aff));
+        let keys = videos.all_items().await.into_iter()
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        videos.remove(&keys).await;
+        purged += keys.len();
+
+        let tombstones = open_cache::<StringId<VideoInfo>, DateTime<Local>>(&tombstone_name(&aff));
+        let keys = tombstones.all_items().await.into_iter()
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        tombstones.remove(&keys).await;
+    }
+    Ok(purged)
+}
+
+/// `cache  entries` per line, with the states of cached videos.
+async fn stats() -> anyhow::Result<()> {
+    println!("backend\t{:?}", get_cache_backend());
+    let etags = cached_etags().await;
+    for name in ["video_search_cache", "video_detail_cache", "ch_search_cache"] {
+        println!("{}\t{}", name, etags.iter().filter(|(cache, _, _)| *cache == name).count());
+    }
+    for aff in affiliations(None)? {
+        let videos = cached_videos(&aff).await;
+        let states = [LiveState::Scheduled, LiveState::Live, LiveState::Ended, LiveState::Cancelled, LiveState::Unknown].into_iter()
+            .map(|state| format!("{}={}", state, videos.iter().filter(|video| video.live_state() == state).count()))
+            .collect::<Vec<_>>();
+        println!("{}\t{}\t{}", video_cache_name(&aff), videos.len(), states.join(" "));
+        let tombstones = open_cache::<StringId<VideoInfo>, DateTime<Local>>(&tombstone_name(&aff)).all_items().await;
+        println!("{}\t{}", tombstone_name(&aff), tombstones.len());
+    }
+    Ok(())
+}
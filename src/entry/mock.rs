@@ -0,0 +1,241 @@
+//! Mock YouTube Data API and API Server, to run the handlers without network access.
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::convert::Infallible;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use hyper::header::{ETAG, IF_NONE_MATCH};
+use hyper::service::{make_service_fn, service_fn};
+use serde_json::{json, Value};
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::Streaming;
+use crate::entry::request::VideoInfo;
+use crate::entry::transport::salmon::{Affiliation, Channel, Live, Liver, TaskResult};
+use crate::entry::transport::salmon::salmon_api_server::{SalmonApi, SalmonApiServer};
+
+/// A request served by [`MockYoutube`].
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub resource: String,
+    pub status: StatusCode
+}
+
+/// Serves `search`, `videos`, `channels` and `playlistItems` from fixtures.
+///
+/// ```text
+/// {fixtures}/search/{channelId}.json          whole response
+/// {fixtures}/channels/{id}.json               whole response
+/// {fixtures}/playlistItems/{playlistId}.json  whole response
+/// {fixtures}/videos/{id}.json                 an item, joined into a response per request
+/// ```
+///
+/// Requests with `pageToken` are served from `{id}@{pageToken}.json` instead.
+///
+/// Missing fixtures are answered with empty `items`, same as the API does for unknown ids.
+/// Requests with `If-None-Match` equal to the `etag` of the response are answered with `304`.
+#[derive(Debug, Clone)]
+pub struct MockYoutube {
+    address: SocketAddr,
+    log: Arc<Mutex<Vec<MockRequest>>>
+}
+
+impl MockYoutube {
+    pub async fn start(fixtures: impl Into<PathBuf>) -> MockYoutube {
+        let fixtures = Arc::new(fixtures.into());
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind mock youtube");
+        let address = listener.local_addr().expect("");
+
+        let shared = (Arc::clone(&fixtures), Arc::clone(&log));
+        let service = make_service_fn(move |_| {
+            let (fixtures, log) = shared.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let res = respond(&fixtures, &req);
+                    log.lock().unwrap().push(MockRequest { resource: resource_of(&req), status: res.status() });
+                    async move { Ok::<_, Infallible>(res) }
+                }))
+            }
+        });
+        let server = Server::from_tcp(listener).expect("").serve(service);
+        tokio::spawn(server);
+        Self { address, log }
+    }
+
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.log.lock().unwrap().clone()
+    }
+
+    pub fn count(&self, resource: &str, status: StatusCode) -> usize {
+        self.requests().iter()
+            .filter(|req| req.resource == resource && req.status == status)
+            .count()
+    }
+}
+
+fn resource_of(req: &Request<Body>) -> String {
+    req.uri().path().rsplit('/').next().unwrap_or_default().to_string()
+}
+
+fn respond(fixtures: &Path, req: &Request<Body>) -> Response<Body> {
+    let query: HashMap<String, String> = serde_urlencoded::from_str(req.uri().query().unwrap_or_default())
+        .unwrap_or_default();
+    if query.get("key").map(String::is_empty).unwrap_or(true) {
+        return error_response(StatusCode::FORBIDDEN, "forbidden", "global", "The request is missing a valid API key.")
+    }
+    let resource = resource_of(req);
+    let param = |name: &str| query.get(name).cloned().unwrap_or_default();
+    let page = |id: String| match query.get("pageToken") {
+        Some(token) => format!("{}@{}.json", id, token),
+        None => format!("{}.json", id)
+    };
+    let body = match resource.as_str() {
+        "search" => load_or_empty(&fixtures.join("search").join(page(param("channelId")))),
+        "channels" => load_or_empty(&fixtures.join("channels").join(format!("{}.json", param("id")))),
+        "playlistItems" => match load(&fixtures.join("playlistItems").join(page(param("playlistId")))) {
+            Some(body) => body,
+            None => return error_response(StatusCode::NOT_FOUND, "playlistNotFound", "youtube.playlistItem", "The playlist identified with the request's playlistId parameter cannot be found.")
+        },
+        "videos" => {
+            let items = param("id").split(',')
+                .map(str::trim)
+                .filter_map(|id| load(&fixtures.join("videos").join(format!("{}.json", id))))
+                .collect::<Vec<Value>>();
+            json!({ "etag": etag_of(&items), "items": items })
+        },
+        _ => return error_response(StatusCode::NOT_FOUND, "notFound", "global", "Not Found")
+    };
+
+    let etag = body.get("etag").and_then(Value::as_str).unwrap_or_default().to_string();
+    let matched = req.headers().get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| !etag.is_empty() && value == etag)
+        .unwrap_or(false);
+    let mut res = if matched {
+        Response::new(Body::empty())
+    } else {
+        Response::new(Body::from(body.to_string()))
+    };
+    if matched {
+        *res.status_mut() = StatusCode::NOT_MODIFIED;
+    }
+    if let Ok(value) = etag.parse() {
+        res.headers_mut().insert(ETAG, value);
+    }
+    res
+}
+
+fn load(path: &Path) -> Option<Value> {
+    std::fs::read_to_string(path).ok()
+        .map(|buf| serde_json::from_str(&buf).expect("broken fixture"))
+}
+
+/// `.test/youtube/videos/Xq3bQ0nTZMg.json` as the video `id`, with `details` as its `liveStreamingDetails`.
+pub fn video(id: &str, details: Value) -> VideoInfo {
+    let mut video = load(Path::new(".test/youtube/videos/Xq3bQ0nTZMg.json")).expect("missing fixture");
+    video["id"] = json!(id);
+    video["liveStreamingDetails"] = details;
+    serde_json::from_value(video).expect("broken fixture")
+}
+
+fn load_or_empty(path: &Path) -> Value {
+    load(path).unwrap_or_else(|| {
+        let items: Vec<Value> = Vec::new();
+        json!({ "etag": etag_of(&items), "items": items })
+    })
+}
+
+fn etag_of(items: &[Value]) -> String {
+    let mut hasher = DefaultHasher::new();
+    Value::from(items.to_vec()).to_string().hash(&mut hasher);
+    format!("mock-{:016x}", hasher.finish())
+}
+
+pub fn error_response(status: StatusCode, reason: &str, domain: &str, message: &str) -> Response<Body> {
+    let body = json!({
+        "error": {
+            "code": status.as_u16(),
+            "message": message,
+            "errors": [{ "message": message, "domain": domain, "reason": reason }]
+        }
+    });
+    let mut res = Response::new(Body::from(body.to_string()));
+    *res.status_mut() = status;
+    res
+}
+
+/// Records everything sent to the API Server.
+#[derive(Debug, Clone, Default)]
+pub struct MockSalmonApi {
+    address: Option<SocketAddr>,
+    lives: Arc<Mutex<Vec<Live>>>,
+    channels: Arc<Mutex<Vec<Channel>>>,
+    livers: Arc<Mutex<Vec<Liver>>>,
+    affiliations: Arc<Mutex<Vec<Affiliation>>>
+}
+
+impl MockSalmonApi {
+    pub async fn start() -> MockSalmonApi {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind mock api server");
+        let mock = Self { address: Some(listener.local_addr().expect("")), ..Self::default() };
+        let server = tonic::transport::Server::builder()
+            .add_service(SalmonApiServer::new(mock.clone()))
+            .serve_with_incoming(TcpListenerStream::new(listener));
+        tokio::spawn(server);
+        mock
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.address.expect("not started"))
+    }
+
+    pub fn lives(&self) -> Vec<Live> {
+        self.lives.lock().unwrap().clone()
+    }
+
+    pub fn channels(&self) -> Vec<Channel> {
+        self.channels.lock().unwrap().clone()
+    }
+
+    pub fn livers(&self) -> Vec<Liver> {
+        self.livers.lock().unwrap().clone()
+    }
+
+    pub fn affiliations(&self) -> Vec<Affiliation> {
+        self.affiliations.lock().unwrap().clone()
+    }
+}
+
+async fn drain<T>(mut stream: Streaming<T>, into: &Mutex<Vec<T>>) -> Result<tonic::Response<TaskResult>, tonic::Status> {
+    while let Some(item) = stream.message().await? {
+        into.lock().unwrap().push(item);
+    }
+    Ok(tonic::Response::new(TaskResult { message: String::from("ok") }))
+}
+
+#[tonic::async_trait]
+impl SalmonApi for MockSalmonApi {
+    async fn insert_req_live(&self, request: tonic::Request<Streaming<Live>>) -> Result<tonic::Response<TaskResult>, tonic::Status> {
+        drain(request.into_inner(), &self.lives).await
+    }
+
+    async fn insert_req_channel(&self, request: tonic::Request<Streaming<Channel>>) -> Result<tonic::Response<TaskResult>, tonic::Status> {
+        drain(request.into_inner(), &self.channels).await
+    }
+
+    async fn insert_req_v_tuber(&self, request: tonic::Request<Streaming<Liver>>) -> Result<tonic::Response<TaskResult>, tonic::Status> {
+        drain(request.into_inner(), &self.livers).await
+    }
+
+    async fn insert_req_affiliation(&self, request: tonic::Request<Streaming<Affiliation>>) -> Result<tonic::Response<TaskResult>, tonic::Status> {
+        drain(request.into_inner(), &self.affiliations).await
+    }
+}
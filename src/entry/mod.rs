@@ -1,20 +1,47 @@
+mod api;
+mod cache;
+mod cassette;
+mod eviction;
+mod feed;
+mod keys;
+mod lifecycle;
+mod maintenance;
+#[cfg(test)]
+mod mock;
+mod quota;
 mod request;
+mod samples;
+mod scheduler;
+mod summary;
 mod transport;
+mod websub;
 
 use std::collections::{HashMap, HashSet};
 use std::collections::vec_deque::VecDeque;
-use std::time::Instant;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use anyhow::Context;
 use async_std::task::block_on;
+use chrono::Local;
 use futures::StreamExt;
-use misery_rs::{CacheWrapper, MiseryHandler};
 use once_cell::sync::OnceCell;
 use regex::Regex;
+use tokio::sync::broadcast;
+use tracing::{debug, error, info, info_span, warn, Instrument};
 use walkdir::{DirEntry, WalkDir};
-use crate::entry::request::{channel_info_request, request_video_info_concurrency, VideoInfo};
+use crate::cli::CacheCommand;
+use crate::entry::cache::open_cache;
+use crate::entry::eviction::{get_tombstone_ttl, get_video_cache_capacity, over_capacity, tombstone_name, Tombstones};
+use crate::entry::keys::get_api_key_pool;
+use crate::entry::lifecycle::{get_cancel_grace, get_lifecycle_store};
+use crate::entry::quota::get_quota_ledger;
+use crate::entry::request::{channel_info_request, request_video_details_with_missing, request_video_info_concurrency, resolve_liver_handles, VideoInfo};
+use crate::entry::summary::RunSummary;
+pub use crate::entry::samples::ExportFormat;
 use crate::entry::transport::{Applier, salmon};
 use crate::entry::transport::salmon::{Affiliation, Liver};
 use crate::ids::StringId;
-use crate::logger::Logger;
 use crate::models::{AffiliationEntry, Channel, LiverEntry};
 
 fn get_regex_for_ignored() -> &'static Regex {
@@ -24,6 +51,13 @@ fn get_regex_for_ignored() -> &'static Regex {
     })
 }
 
+/// Whether the video is sent to API Server. Finished, too far scheduled and free chat frames are excluded.
+fn is_sync_target(video: &VideoInfo) -> bool {
+    !video.is_live_finished()
+        && !video.is_too_long_span_live()
+        && !get_regex_for_ignored().is_match(video.as_ref_title())
+}
+
 fn is_ignored_file(entry: &DirEntry, ignore: impl Into<String>) -> bool {
     entry.file_name().to_str()
         .map(|name| name.ends_with(&ignore.into()))
@@ -36,133 +70,595 @@ fn is_json(entry: &DirEntry) -> bool {
         .unwrap_or(false)
 }
 
+fn get_cache_dir() -> &'static str {
+    static DIR: OnceCell<String> = OnceCell::new();
+    DIR.get_or_init(|| {
+        dotenv::var("CACHE_PATH")
+            .unwrap_or_else(|_| String::from("./.cache"))
+    })
+}
+
+/// File in the cache directory, `./.cache` or `CACHE_PATH`.
+pub(crate) fn cache_path(name: impl AsRef<str>) -> String {
+    format!("{}/{}", get_cache_dir(), name.as_ref())
+}
+
+/// Run a file write on a blocking thread of the runtime, or in place outside of a runtime.
+pub(crate) fn write_blocking(write: impl FnOnce() + Send + 'static) {
+    match tokio::runtime::Handle::try_current() {
+        Ok(runtime) => drop(runtime.spawn_blocking(write)),
+        Err(_) => write()
+    }
+}
+
+/// Videos sent for the affiliation.
+pub(crate) fn video_cache_name(aff: &AffiliationEntry) -> String {
+    format!("video_info_{}_cache", aff.as_ref_name())
+}
+
+fn get_config_dir() -> &'static str {
+    static DIR: OnceCell<String> = OnceCell::new();
+    DIR.get_or_init(|| {
+        dotenv::var("CONFIG_PATH")
+            .unwrap_or_else(|_| String::from("./.config"))
+    })
+}
+
+/// Affiliations in the config, without sending them to API Server.
+pub(crate) fn load_affiliations() -> anyhow::Result<VecDeque<AffiliationEntry>> {
+    AffiliationEntry::load_from(format!("{}/affiliation.json", get_config_dir()))
+}
+
 pub fn get_or_init_config() -> &'static HashMap<AffiliationEntry, HashSet<LiverEntry>> {
     static LOCKED: OnceCell<HashMap<AffiliationEntry, HashSet<LiverEntry>>> = OnceCell::new();
     LOCKED.get_or_init(|| {
-        let logger = Logger::new(Some("Init Lock"));
-        logger.debug("Initialize >>>");
+        let _span = info_span!("Init Lock").entered();
+        debug!("Initialize >>>");
         let total = Instant::now();
-        let path = dotenv::var("CONFIG_PATH")
-            .unwrap_or_else(|_| String::from("./.config"));
+        let path = get_config_dir();
         let mut maps: HashMap<AffiliationEntry, HashSet<LiverEntry>> = HashMap::new();
-        AffiliationEntry::load_from(format!("{}/affiliation.json", &path))
+        load_affiliations()
             .expect("not found affiliation config").into_iter()
             .for_each(|affiliation| {
-                logger.debug(format!("Loading << {}", affiliation.as_ref_name()));
+                let _span = info_span!("Init Lock", affiliation = %affiliation.as_ref_name()).entered();
+                debug!("Loading << {}", affiliation.as_ref_name());
                 let timer = Instant::now();
                 let mut lives = HashSet::new();
                 WalkDir::new(format!("{}/{}", path, affiliation.as_ref_name())).into_iter()
                     .filter_map(|entry| entry.ok())
                     .filter(|entry| is_json(entry) && !is_ignored_file(entry, affiliation.as_ref_name()))
                     .for_each(|liver| {
-                        let item = LiverEntry::load_from(liver.path())
+                        let mut item = LiverEntry::load_from(liver.path())
                             .expect("failed load liver config");
-                        logger.debug(format!(" + {}", item.as_ref_id().as_ref()));
+                        block_on(resolve_liver_handles(&mut item));
+                        debug!(liver = %item.as_ref_id(), " + {}", item.as_ref_id().as_ref());
                         lives.insert(item);
                     });
-                logger.debug(format!("Loaded affiliation:{}/livers:{}", affiliation.as_ref_name(), lives.len()));
+                debug!("Loaded affiliation:{}/livers:{}", affiliation.as_ref_name(), lives.len());
                 maps.insert(affiliation, lives);
-                logger.debug(format!("Finished >> {}ms", timer.elapsed().as_millis()));
+                debug!("Finished >> {}ms", timer.elapsed().as_millis());
             });
-        logger.debug("Start send base data to API Server >>");
+        debug!("Start send base data to API Server >>");
         let timer = Instant::now();
-        let mut client = block_on(transport::build_client())
-            .expect("build_grpc_client");
-        let client = &mut client;
-        logger.debug("client built");
-        match block_on(client.insert_req_affiliation(tonic::Request::new(futures::stream::iter(maps.clone().into_iter()
-            .map(|(aff, _)| Affiliation::from(aff))
-            .collect::<Vec<_>>())))) {
-            Ok(_) => logger.debug("affiliation base info finished."),
-            Err(reason) => logger.error(format!("failed task: {}", reason))
-        };
+        // the loaded config is still used when API Server is down, base data is sent again on the next start.
+        match block_on(transport::build_client()) {
+            Ok(mut client) => {
+                let client = &mut client;
+                debug!("client built");
+                match block_on(client.insert_req_affiliation(tonic::Request::new(futures::stream::iter(maps.clone().into_iter()
+                    .map(|(aff, _)| Affiliation::from(aff))
+                    .collect::<Vec<_>>())))) {
+                    Ok(_) => debug!("affiliation base info finished."),
+                    Err(reason) => error!("failed task: {}", reason)
+                };
 
-        match block_on(client.insert_req_v_tuber(tonic::Request::new(futures::stream::iter(maps.clone().into_iter()
-            .flat_map(|(aff, livers)| livers.into_iter()
-                .map(move |base| Liver::from(base).apply(&aff)))
-            .collect::<Vec<_>>())))) {
-            Ok(_) => logger.debug("liver base info finished."),
-            Err(reason) => logger.error(format!("failed task: {}", reason))
-        };
-        logger.debug(format!("finished << {}sec", timer.elapsed().as_secs_f32()));
-        logger.debug(format!("Total elapsed <<< {}sec", total.elapsed().as_secs_f32()));
+                match block_on(client.insert_req_v_tuber(tonic::Request::new(futures::stream::iter(maps.clone().into_iter()
+                    .flat_map(|(aff, livers)| livers.into_iter()
+                        .map(move |base| Liver::from(base).apply(&aff)))
+                    .collect::<Vec<_>>())))) {
+                    Ok(_) => debug!("liver base info finished."),
+                    Err(reason) => error!("failed task: {}", reason)
+                };
+            },
+            Err(reason) => error!("failed task, base data is not sent: {:?}", reason)
+        }
+        debug!("finished << {}sec", timer.elapsed().as_secs_f32());
+        debug!("Total elapsed <<< {}sec", total.elapsed().as_secs_f32());
         maps
     })
 }
 
-pub async fn channel_info_request_handler() -> anyhow::Result<()> {
-    let logger = Logger::new(Some("Request"));
+#[tracing::instrument(name = "Request", skip_all)]
+pub async fn channel_info_request_handler() -> anyhow::Result<RunSummary> {
     let total = Instant::now();
 
-    futures::stream::iter(get_or_init_config()).for_each(|(aff, liver)| async move {
-        let logger = Logger::new(Some("Request"));
-        let mut client = transport::build_client().await
-            .expect("build_grpc_client");
-        let client = &mut client;
+    let summary = futures::stream::iter(get_or_init_config()).map(|(aff, liver)| scheduler::in_lane(aff.as_ref_name(), async move {
+        let mut summary = RunSummary::default();
 
-        logger.info(format!("Liver Info Retrieve {}", aff.as_ref_name()));
-        let infos = channel_info_request(liver).await
-            .expect("channel_info_request");
+        info!("Liver Info Retrieve {}", aff.as_ref_name());
+        let infos = match channel_info_request(liver, &mut summary).await {
+            Ok(infos) => infos,
+            Err(reason) => {
+                error!("skip {}: {:?}", aff.as_ref_name(), reason);
+                return summary
+            }
+        };
 
-        let send = infos.into_iter()
-            .map(salmon::Channel::from)
-            .collect::<VecDeque<_>>();
-        let applied = send.iter().map(|trans| trans.to_owned().apply(
-            liver.iter()
-                .find(|person| person.as_ref_site().iter()
-                    .flat_map(Channel::as_youtube_id)
-                    .any(|id| id.as_ref() == trans.channel_id))
-                .unwrap()))
+        let applied = infos.into_iter()
+            .filter_map(|info| {
+                let id = match StringId::<Channel>::try_from(info.breach_extraction_id()) {
+                    Ok(id) => id,
+                    Err(reason) => {
+                        warn!("skipped channel: {}", reason);
+                        return None
+                    }
+                };
+                let owner = liver.iter()
+                    .find(|person| person.as_ref_site().iter()
+                        .flat_map(Channel::as_youtube_id)
+                        .any(|owned| owned == id));
+                match owner {
+                    Some(owner) => Some(salmon::Channel::from(info).apply(owner)),
+                    None => {
+                        warn!(channel = %id, "no liver of {} owns the channel, skipped.", aff.as_ref_name());
+                        None
+                    }
+                }
+            })
             .collect::<Vec<_>>();
 
+        let mut client = match transport::build_client().await {
+            Ok(client) => client,
+            Err(reason) => {
+                error!("failed task: {:?}", reason);
+                return summary
+            }
+        };
+        let client = &mut client;
         let stream_req = tonic::Request::new(futures::stream::iter(applied));
         match client.clone().insert_req_channel(stream_req).await {
             Ok(_) => (),
-            Err(reason) => println!("{}", reason)
+            Err(reason) => error!("failed task: {}", reason)
         };
-    }).await;
-    logger.info(format!("Total elapsed >>> {}sec", total.elapsed().as_secs_f32()));
-    Ok(())
+        summary
+    }.instrument(info_span!("Request", affiliation = %aff.as_ref_name()))))
+        .buffer_unordered(get_or_init_config().len().max(1))
+        .fold(RunSummary::default(), |mut total, summary| async move {
+            total.merge(summary);
+            total
+        }).await;
+    info!("Total elapsed >>> {}sec", total.elapsed().as_secs_f32());
+    summary.report();
+    get_quota_ledger().report();
+    get_api_key_pool().report();
+    Ok(summary)
 }
 
-pub async fn upcoming_live_request_handler() -> anyhow::Result<()> {
-    let logger = Logger::new(Some("Request"));
+#[tracing::instrument(name = "Request", skip_all)]
+pub async fn upcoming_live_request_handler() -> anyhow::Result<RunSummary> {
     let total = Instant::now();
 
-    futures::stream::iter(get_or_init_config().iter()).for_each(|(aff, liver)| async move {
-        let logger = Logger::new(Some("Request"));
-        let caching: MiseryHandler<StringId<VideoInfo>, VideoInfo> = MiseryHandler::load_from_blocking(format!("./.cache/video_info_{}_cache.json", aff.as_ref_name()));
-        let mut client = transport::build_client().await
-            .expect("build_grpc_client");
-        let client = &mut client;
+    let summary = futures::stream::iter(get_or_init_config().iter()).map(|(aff, liver)| scheduler::in_lane(aff.as_ref_name(), async move {
+        let mut summary = RunSummary::default();
         let request = Instant::now();
-        logger.info(format!("Request << {}", aff.as_ref_name()));
-        let video_infos = request_video_info_concurrency(liver).await
-            .expect("failed req.").into_iter()
-            .filter(|video| !video.is_live_finished())
-            .filter(|video| !video.is_too_long_span_live())
-            .filter(|video| !get_regex_for_ignored().is_match(video.as_ref_title()))
-            .inspect(|video| logger.debug(format!("{}: {}", video.as_ref_id(), video.as_ref_title())))
+        info!("Request << {}", aff.as_ref_name());
+        let mut video_infos = match request_video_info_concurrency(liver, &format!("discovery_{}", aff.as_ref_name()), &mut summary).await {
+            Ok(videos) => videos,
+            Err(reason) => {
+                error!("skip {}: {}", aff.as_ref_name(), reason);
+                summary.fail_details(reason);
+                return summary
+            }
+        };
+        let (refreshed, missing) = match refresh_tracked_lives(aff, &mut summary).await {
+            Some(refreshed) => refreshed,
+            None => return summary
+        };
+        video_infos.extend(refreshed);
+        let video_infos = video_infos.into_iter()
+            .inspect(|video| debug!(video = %video.as_ref_id(), state = %video.live_state(), "{}", video.as_ref_title()))
             .collect::<VecDeque<VideoInfo>>();
-        let delete = caching.all_items().await.into_iter()
-            .filter(|valid| valid.as_ref_value().is_live_finished())
-            .map(|del| salmon::Live::from(del.value()).del_sign())
-            .collect::<VecDeque<_>>();
-        video_infos.clone().into_iter().for_each(|video| {
-            let caching = &caching;
-            block_on(caching.abs(CacheWrapper::new(video.as_ref_id().to_owned(), video)));
-        });
-        logger.info(format!("Finished {} >> {}sec", aff.as_ref_name(), request.elapsed().as_secs_f32()));
-        let send = video_infos.into_iter()
-            .map(salmon::Live::from)
-            .chain(delete)
+        info!("Finished {} >> {}sec", aff.as_ref_name(), request.elapsed().as_secs_f32());
+        push_lives(aff, video_infos, missing).await;
+        summary
+    }.instrument(info_span!("Request", affiliation = %aff.as_ref_name()))))
+        .buffer_unordered(get_or_init_config().len().max(1))
+        .fold(RunSummary::default(), |mut total, summary| async move {
+            total.merge(summary);
+            total
+        }).await;
+    info!("Total elapsed >>> {}sec", total.elapsed().as_secs_f32());
+    summary.report();
+    get_quota_ledger().report();
+    get_api_key_pool().report();
+    Ok(summary)
+}
+
+/// Details of cached videos which have not finished, and ids of them which were not returned.
+///
+/// Search discovery returns only upcoming streams, so that their states are never updated without this,
+/// and deleted or private reservations are just left out of the responses.
+/// `None` when they can not be looked up, the failure is recorded in `summary`.
+async fn refresh_tracked_lives(aff: &AffiliationEntry, summary: &mut RunSummary) -> Option<(HashSet<VideoInfo>, HashSet<StringId<VideoInfo>>)> {
+    let caching = open_cache::<StringId<VideoInfo>, VideoInfo>(&video_cache_name(aff));
+    let tracked = caching.all_items().await.into_iter()
+        .map(|(_, video)| video)
+        .filter(|video| !video.is_live_finished())
+        .map(|video| video.as_ref_id().to_owned())
+        .collect::<VecDeque<_>>();
+    if tracked.is_empty() {
+        return Some((HashSet::new(), HashSet::new()))
+    }
+    debug!("refresh {} tracked videos.", tracked.len());
+    match request_video_details_with_missing(tracked, Some(&format!("tracked_{}", aff.as_ref_name())), summary).await {
+        Ok(refreshed) => Some(refreshed),
+        Err(reason) => {
+            error!("skip {}, cannot refresh tracked videos: {}", aff.as_ref_name(), reason);
+            summary.fail_details(reason);
+            None
+        }
+    }
+}
+
+/// Lock of the affiliation, held while its caches and lifecycles are updated and sent.
+///
+/// The periodic request and WebSub notifications push the same affiliation concurrently,
+/// and caches are updated by find-then-put.
+async fn lock_affiliation(aff: &AffiliationEntry) -> tokio::sync::OwnedMutexGuard<()> {
+    static LOCKS: OnceCell<std::sync::Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>> = OnceCell::new();
+    let lock = LOCKS.get_or_init(Default::default).lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .entry(aff.as_ref_name().to_string())
+        .or_default()
+        .clone();
+    lock.lock_owned().await
+}
+
+/// Update the states of observed videos, cache the sync targets and return them to be sent.
+///
+/// Videos which are not sync targets only update their cached copy, when they are cached already.
+/// Buried videos, and the others which are neither sync targets nor cached, are skipped without lifecycle.
+async fn observe_lives(aff: &AffiliationEntry, video_infos: VecDeque<VideoInfo>) -> Vec<salmon::Live> {
+    let caching = open_cache::<StringId<VideoInfo>, VideoInfo>(&video_cache_name(aff));
+    let tombstones = Tombstones::new(open_cache(&tombstone_name(aff)));
+    let lifecycle = get_lifecycle_store();
+    let mut send = Vec::new();
+    for video in video_infos {
+        if tombstones.is_buried(video.as_ref_id(), *get_tombstone_ttl()).await {
+            debug!(video = %video.as_ref_id(), "deletion was sent already, skipped.");
+            continue
+        }
+        let target = is_sync_target(&video);
+        if !target && caching.find_value(video.as_ref_id()).await.is_none() {
+            continue
+        }
+        lifecycle.observe(&video).await;
+        caching.put(video.as_ref_id().to_owned(), video.clone()).await;
+        if target {
+            let record = lifecycle.find(video.as_ref_id()).await;
+            let live = salmon::Live::from(video);
+            send.push(match record {
+                Some(record) => live.apply(&record),
+                None => live
+            });
+        }
+    }
+    send
+}
+
+/// Send `lives` to API Server, and return whether it was acknowledged.
+async fn send_lives(lives: Vec<salmon::Live>) -> bool {
+    let mut client = match transport::build_client().await {
+        Ok(client) => client,
+        Err(reason) => {
+            error!("failed task: {:?}", reason);
+            return false
+        }
+    };
+    let client = &mut client;
+    let stream_req = tonic::Request::new(futures::stream::iter(lives));
+    match client.clone().insert_req_live(stream_req).await {
+        Ok(_) => true,
+        Err(reason) => {
+            error!("failed task: {}", reason);
+            false
+        }
+    }
+}
+
+/// Remove the videos from the cache of the affiliation, with their lifecycles.
+async fn evict_lives(aff: &AffiliationEntry, videos: &[StringId<VideoInfo>]) {
+    if videos.is_empty() {
+        return
+    }
+    open_cache::<StringId<VideoInfo>, VideoInfo>(&video_cache_name(aff)).remove(videos).await;
+    get_lifecycle_store().forget(videos).await;
+}
+
+/// Bury the deleted videos acknowledged by API Server, and evict them.
+async fn bury_lives(aff: &AffiliationEntry, deleted: HashSet<StringId<VideoInfo>>) {
+    let deleted = deleted.into_iter().collect::<Vec<_>>();
+    Tombstones::new(open_cache(&tombstone_name(aff))).bury(deleted.iter().cloned(), Local::now()).await;
+    evict_lives(aff, &deleted).await;
+}
+
+/// Update the states of observed videos, cache and send the sync targets, with deletion of finished lives in cache.
+///
+/// `missing` videos are cancelled after [`get_cancel_grace`], then sent with deletion.
+/// Deleted videos are evicted from the cache and buried once API Server acknowledged them.
+/// Then the cache is cut down to [`get_video_cache_capacity`], and the evicted videos are sent with deletion too,
+/// so that API Server never keeps a reservation which is no longer tracked.
+async fn push_lives(aff: &AffiliationEntry, video_infos: VecDeque<VideoInfo>, missing: HashSet<StringId<VideoInfo>>) {
+    let _lock = lock_affiliation(aff).await;
+    let caching = open_cache::<StringId<VideoInfo>, VideoInfo>(&video_cache_name(aff));
+    let tombstones = Tombstones::new(open_cache(&tombstone_name(aff)));
+    let lifecycle = get_lifecycle_store();
+    let mut send = observe_lives(aff, video_infos).await;
+    let mut deleted = HashSet::new();
+    for id in missing {
+        if !lifecycle.missing(&id, *get_cancel_grace()).await {
+            continue
+        }
+        if let Some(video) = caching.find_value(&id).await {
+            send.push(salmon::Live::from(video).del_sign());
+        }
+        deleted.insert(id);
+    }
+    let finished = caching.all_items().await.into_iter()
+        .map(|(_, video)| video)
+        .filter(|video| !deleted.contains(video.as_ref_id()) && video.is_live_finished())
+        .collect::<Vec<_>>();
+    for video in finished {
+        deleted.insert(video.as_ref_id().to_owned());
+        send.push(salmon::Live::from(video).del_sign());
+    }
+    // deletions are kept in the cache, and sent again on the next run.
+    if send_lives(send).await {
+        bury_lives(aff, deleted).await;
+
+        let remaining = caching.all_items().await.into_iter()
+            .map(|(_, video)| video)
             .collect::<Vec<_>>();
-        let stream_req = tonic::Request::new(futures::stream::iter(send));
-        match client.clone().insert_req_live(stream_req).await {
-            Ok(_) => (),
-            Err(reason) => println!("{}", reason)
-        };
-    }).await;
-    logger.info(format!("Total elapsed >>> {}sec", total.elapsed().as_secs_f32()));
+        let evicted = over_capacity(&remaining, *get_video_cache_capacity(), Local::now()).into_iter()
+            .collect::<HashSet<_>>();
+        if !evicted.is_empty() {
+            warn!("{} videos are over the cache capacity of {}, sent with deletion and evicted.", evicted.len(), aff.as_ref_name());
+            let deletions = remaining.into_iter()
+                .filter(|video| evicted.contains(video.as_ref_id()))
+                .map(|video| salmon::Live::from(video).del_sign())
+                .collect::<Vec<_>>();
+            // not buried, so that they are sent again once discovered again.
+            if send_lives(deletions).await {
+                evict_lives(aff, &evicted.into_iter().collect::<Vec<_>>()).await;
+            }
+        }
+    }
+    let pruned = tombstones.prune(*get_tombstone_ttl()).await;
+    lifecycle.forget(&pruned).await;
+}
+
+/// Update the states of notified videos, and send them only.
+///
+/// Notified videos which have finished are sent with deletion when they are cached.
+/// Cancellations, the other cached videos and the capacity are left to the periodic request.
+async fn push_notified_lives(aff: &AffiliationEntry, video_infos: VecDeque<VideoInfo>) {
+    let _lock = lock_affiliation(aff).await;
+    let caching = open_cache::<StringId<VideoInfo>, VideoInfo>(&video_cache_name(aff));
+    let notified = video_infos.iter()
+        .map(|video| video.as_ref_id().to_owned())
+        .collect::<Vec<_>>();
+    let mut send = observe_lives(aff, video_infos).await;
+    let mut deleted = HashSet::new();
+    for id in notified {
+        if let Some(video) = caching.find_value(&id).await.filter(VideoInfo::is_live_finished) {
+            send.push(salmon::Live::from(video).del_sign());
+            deleted.insert(id);
+        }
+    }
+    if !send.is_empty() && send_lives(send).await {
+        bury_lives(aff, deleted).await;
+    }
+}
+
+/// Interval of the periodic request while the process is kept up, `POLL_INTERVAL_SECS` (default: 600).
+fn get_poll_interval() -> &'static Duration {
+    static INTERVAL: OnceCell<Duration> = OnceCell::new();
+    INTERVAL.get_or_init(|| {
+        dotenv::var("POLL_INTERVAL_SECS")
+            .ok()
+            .and_then(|f| f.parse().ok())
+            .filter(|secs: &u64| *secs > 0)
+            .map(Duration::from_secs)
+            .unwrap_or_else(|| Duration::from_secs(600))
+    })
+}
+
+/// Repeat [`upcoming_live_request_handler`] while WebSub receiver or live sampler keeps the process up.
+///
+/// Feeds notify uploads and edits, but not the start and the end of lives reliably,
+/// so that transitions, cancellations and finished lives in cache are still found by polling.
+/// Returns at once when the process runs only one request.
+#[tracing::instrument(name = "polling", skip_all)]
+pub async fn polling_handler() -> anyhow::Result<()> {
+    if websub::get_callback_url().is_none() && samples::get_sample_interval().is_none() {
+        return Ok(())
+    }
+    let interval = *get_poll_interval();
+    info!("request upcoming lives every {}sec", interval.as_secs());
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        upcoming_live_request_handler().await?;
+    }
+}
+
+/// Serve WebSub receiver when `WEBSUB_CALLBACK_URL` is set. Does not return while serving.
+#[tracing::instrument(name = "websub", skip_all)]
+pub async fn websub_handler() -> anyhow::Result<()> {
+    if websub::get_callback_url().is_none() {
+        debug!("WEBSUB_CALLBACK_URL is not set, WebSub receiver is disabled.");
+        return Ok(())
+    }
+    websub::serve().await
+}
+
+/// Sample live streams when `SAMPLE_INTERVAL_SECS` is set. Does not return while sampling.
+#[tracing::instrument(name = "sampler", skip_all)]
+pub async fn sampler_handler() -> anyhow::Result<()> {
+    match samples::get_sample_interval() {
+        Some(interval) => samples::run(*interval).await,
+        None => {
+            debug!("SAMPLE_INTERVAL_SECS is not set, live sampler is disabled.");
+            Ok(())
+        }
+    }
+}
+
+/// Log transitions of lives observed by polling and WebSub. Does not return.
+///
+/// Subscribed on the call, so that transitions of the first run are not missed while it is spawned.
+pub fn lifecycle_handler() -> impl std::future::Future<Output = ()> {
+    let mut events = lifecycle::subscribe();
+    async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => info!(video = %event.as_ref_video(), transition = %event.as_ref_transition(),
+                    "{} {}", event.as_ref_video().as_ref(), event.as_ref_transition()),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => warn!("{} transitions were not logged.", skipped),
+                Err(broadcast::error::RecvError::Closed) => return
+            }
+        }
+    }.instrument(info_span!("lifecycle"))
+}
+
+/// Write recorded samples to `out`, or to stdout.
+pub async fn export_samples(format: ExportFormat, video: Option<String>, out: Option<PathBuf>) -> anyhow::Result<()> {
+    let video = video.map(StringId::<VideoInfo>::new);
+    let store = samples::get_sample_store();
+    let count = match out {
+        Some(path) => store.export(format, video.as_ref(), std::fs::File::create(&path)
+            .with_context(|| format!("cannot create {}", path.display()))?).await?,
+        None => store.export(format, video.as_ref(), std::io::stdout().lock()).await?
+    };
+    eprintln!("exported {} samples.", count);
     Ok(())
 }
+
+/// Inspect or clean the caches of etags and videos.
+pub async fn cache_command(command: CacheCommand) -> anyhow::Result<()> {
+    maintenance::run(command).await
+}
+
+#[cfg(test)]
+mod entry_test {
+    use std::collections::VecDeque;
+    use hyper::StatusCode;
+    use serde_json::json;
+    use crate::entry::{channel_info_request_handler, get_or_init_config, mock, observe_lives, upcoming_live_request_handler};
+    use crate::entry::lifecycle::{subscribe, Transition};
+    use crate::entry::request::{cached_etags, purge_etags, request_video_details, request_video_details_with_missing};
+    use crate::entry::summary::RunSummary;
+    use crate::ids::StringId;
+    use crate::entry::mock::{MockSalmonApi, MockYoutube};
+
+    /// Runs both handlers against `.test/config` and `.test/youtube`.
+    ///
+    /// Configuration is read once per process, so the whole pipeline is checked in this single test.
+    /// Upcoming fixtures are scheduled in 2099, so that they never become finished lives.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn handler_pipeline_test() {
+        let youtube = MockYoutube::start(".test/youtube").await;
+        let server = MockSalmonApi::start().await;
+        let cache = std::env::temp_dir().join(format!("salmon_entry_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&cache);
+        std::env::set_var("YOUTUBE_API_BASE_URL", youtube.base_url());
+        std::env::set_var("MATATABI_SERVER", server.url());
+        std::env::set_var("CONFIG_PATH", ".test/config");
+        std::env::set_var("CACHE_PATH", &cache);
+        std::env::set_var("API_KEYS", "mock-key");
+        std::env::set_var("DISCOVERY_STRATEGY", "search");
+
+        let summary = channel_info_request_handler().await.expect("");
+        assert!(summary.is_clean());
+        assert_eq!(server.affiliations().len(), 1);
+        assert_eq!(server.livers().len(), 2);
+        let channels = server.channels();
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].channel_id, "UCvaTdHTWBGv3MKj3KVqJVCw");
+        assert_eq!(channels[0].liver_id, Some(2873092578926472));
+
+        // archive and free chat are filtered out, and the second page of search is followed.
+        let summary = upcoming_live_request_handler().await.expect("");
+        assert!(summary.is_clean());
+        let lives = server.lives();
+        let mut live_ids = lives.iter().map(|live| live.video_id.as_str()).collect::<Vec<_>>();
+        live_ids.sort();
+        assert_eq!(live_ids, vec!["Xq3bQ0nTZMg", "pG2wAiTiNg0"]);
+        assert!(lives.iter().all(|live| live.will_start_at.is_some()));
+        // statistics and viewers of waiting room are forwarded.
+        let waiting = lives.iter().find(|live| live.video_id == "Xq3bQ0nTZMg").expect("");
+        assert_eq!(waiting.like_count, 120);
+        assert_eq!(waiting.concurrent_viewers, Some(1532));
+        assert!(lives.iter().any(|live| live.concurrent_viewers.is_none()));
+        assert_eq!(youtube.count("search", StatusCode::OK), 3);
+
+        // cached etag is answered with 304 on the next run, and paginated results are downloaded again.
+        upcoming_live_request_handler().await.expect("");
+        assert_eq!(youtube.count("search", StatusCode::NOT_MODIFIED), 1);
+        assert_eq!(youtube.count("search", StatusCode::OK), 5);
+        channel_info_request_handler().await.expect("");
+        assert_eq!(youtube.count("channels", StatusCode::NOT_MODIFIED), 2);
+
+        // details of the same ids are not downloaded again in the same scope, regardless of the order.
+        let ids = ["FrEeChAt001", "pG2wAiTiNg0", "Xq3bQ0nTZMg", "7kAa2Yh3z_Q", "Xq3bQ0nTZMg"].into_iter()
+            .map(StringId::new)
+            .collect::<VecDeque<_>>();
+        let not_modified = youtube.count("videos", StatusCode::NOT_MODIFIED);
+        let details = request_video_details(ids.clone(), Some("test"), &mut RunSummary::default()).await.expect("");
+        assert_eq!(details.len(), 4);
+        let reversed = ids.iter().rev().cloned().collect::<VecDeque<_>>();
+        let details = request_video_details(reversed, Some("test"), &mut RunSummary::default()).await.expect("");
+        assert!(details.is_empty());
+        assert_eq!(youtube.count("videos", StatusCode::NOT_MODIFIED), not_modified + 1);
+        let details = request_video_details(ids.clone(), None, &mut RunSummary::default()).await.expect("");
+        assert_eq!(details.len(), 4);
+        // etags of the batches that no longer match are dropped, so that one is left in the scope.
+        let single = VecDeque::from([StringId::new("Xq3bQ0nTZMg")]);
+        let details = request_video_details(single, Some("test"), &mut RunSummary::default()).await.expect("");
+        assert_eq!(details.len(), 1);
+        let scoped = cached_etags().await.into_iter()
+            .filter(|(name, key, _)| *name == "video_detail_cache" && key.starts_with("test:"))
+            .map(|(_, key, _)| key)
+            .collect::<Vec<_>>();
+        assert_eq!(scoped, vec!["test:Xq3bQ0nTZMg"]);
+        let details = request_video_details(ids, Some("test"), &mut RunSummary::default()).await.expect("");
+        assert_eq!(details.len(), 4);
+
+        // deleted or private videos are reported as missing, and asked again on the next lookup.
+        for _ in 0..2 {
+            let ids = VecDeque::from([StringId::new("Xq3bQ0nTZMg"), StringId::new("DeLeTeD0001")]);
+            let (details, missing) = request_video_details_with_missing(ids, Some("tracked_mock"), &mut RunSummary::default()).await.expect("");
+            assert_eq!(details.len(), 1);
+            assert_eq!(missing.into_iter().collect::<Vec<_>>(), vec![StringId::new("DeLeTeD0001")]);
+        }
+
+        // purged etags are not sent, and full responses are downloaded again.
+        assert!(purge_etags().await > 0);
+        upcoming_live_request_handler().await.expect("");
+        assert_eq!(youtube.count("search", StatusCode::OK), 8);
+
+        // transitions found by the sync path reach subscribers.
+        let mut events = subscribe();
+        let aff = get_or_init_config().keys().next().expect("");
+        let moved = mock::video("Xq3bQ0nTZMg", json!({ "scheduledStartTime": "2099-01-03T12:00:00Z" }));
+        let to = *moved.as_ref_live_streaming_details().as_ref_scheduled_start_time_optional();
+        assert_eq!(observe_lives(aff, VecDeque::from([moved])).await.len(), 1);
+        let rescheduled = std::iter::from_fn(|| events.try_recv().ok())
+            .filter(|event| event.as_ref_video().as_ref() == "Xq3bQ0nTZMg")
+            .any(|event| matches!(event.as_ref_transition(), Transition::Rescheduled { to: moved, .. } if Some(*moved) == to));
+        assert!(rescheduled);
+
+        let _ = std::fs::remove_dir_all(&cache);
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use chrono::{NaiveDate, Utc};
+use chrono_tz::US::Pacific;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use crate::entry::{cache_path, write_blocking};
+use crate::entry::keys::get_api_key_pool;
+
+const DEFAULT_DAILY_BUDGET: u64 = 10_000;
+
+/// YouTube Data API v3 endpoints and their quota cost.
+///
+/// See: https://developers.google.com/youtube/v3/determine_quota_cost
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Endpoint {
+    Search,
+    Videos,
+    Channels,
+    PlaylistItems
+}
+
+impl Endpoint {
+    pub fn cost(&self) -> u64 {
+        match self {
+            Endpoint::Search => 100,
+            Endpoint::Videos | Endpoint::Channels | Endpoint::PlaylistItems => 1
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Endpoint::Search => "search",
+            Endpoint::Videos => "videos",
+            Endpoint::Channels => "channels",
+            Endpoint::PlaylistItems => "playlistItems"
+        }
+    }
+}
+
+/// Low priority requests are refused while the remaining quota is within the reserve.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Priority {
+    High,
+    Low
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum QuotaError {
+    #[error("daily quota budget exhausted. ({}/{} units used)", .used, .budget)]
+    Exhausted { used: u64, budget: u64 },
+    #[error("deferred low priority request, {} units are reserved. ({}/{} units used)", .reserve, .used, .budget)]
+    Deferred { used: u64, reserve: u64, budget: u64 },
+}
+
+pub fn get_quota_ledger() -> &'static QuotaLedger {
+    static LEDGER: OnceCell<QuotaLedger> = OnceCell::new();
+    LEDGER.get_or_init(|| {
+        let budget = dotenv::var("QUOTA_DAILY_BUDGET")
+            .ok()
+            .and_then(|f| f.parse().ok())
+            .unwrap_or(DEFAULT_DAILY_BUDGET * get_api_key_pool().len().max(1) as u64);
+        let reserve = dotenv::var("QUOTA_LOW_PRIORITY_RESERVE")
+            .ok()
+            .and_then(|f| f.parse().ok())
+            .unwrap_or(budget / 10);
+        let metrics = dotenv::var("QUOTA_METRICS_PATH")
+            .ok()
+            .map(PathBuf::from);
+        QuotaLedger::load(cache_path("quota.json"), budget, reserve, metrics)
+    })
+}
+
+/// Date of YouTube quota, which is reset at midnight Pacific Time.
+pub fn quota_day() -> NaiveDate {
+    Utc::now().with_timezone(&Pacific).date_naive()
+}
+
+/// Counts quota units spent per run and per Pacific-time day.
+///
+/// The daily count is persisted to `quota.json` in the cache directory after charges,
+/// and also exported in prometheus text format when `QUOTA_METRICS_PATH` is set.
+/// Files are written on a blocking thread, charges made while a write is scheduled are written together.
+#[derive(Debug)]
+pub struct QuotaLedger {
+    path: PathBuf,
+    metrics: Option<PathBuf>,
+    budget: u64,
+    reserve: u64,
+    state: Arc<Mutex<LedgerState>>,
+    pending: Arc<AtomicBool>,
+    writer: Arc<Mutex<()>>
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct LedgerState {
+    day: Option<NaiveDate>,
+    used: u64,
+    #[serde(default)]
+    by_endpoint: BTreeMap<Endpoint, u64>,
+    #[serde(skip)]
+    run: u64
+}
+
+impl LedgerState {
+    fn roll_over(&mut self, today: NaiveDate) {
+        if self.day != Some(today) {
+            self.day = Some(today);
+            self.used = 0;
+            self.by_endpoint.clear();
+        }
+    }
+}
+
+impl QuotaLedger {
+    pub fn load(path: impl Into<PathBuf>, budget: u64, reserve: u64, metrics: Option<PathBuf>) -> QuotaLedger {
+        let path = path.into();
+        let state = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|buf| serde_json::from_str::<LedgerState>(&buf).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            metrics,
+            budget,
+            reserve,
+            state: Arc::new(Mutex::new(state)),
+            pending: Arc::new(AtomicBool::new(false)),
+            writer: Arc::new(Mutex::new(()))
+        }
+    }
+
+    /// Charge the cost of `endpoint` before sending the request.
+    ///
+    /// YouTube charges quota even for failed or `304 Not Modified` requests,
+    /// so the units are not refunded.
+    pub fn try_spend(&self, endpoint: Endpoint, priority: Priority) -> Result<(), QuotaError> {
+        let mut state = self.state.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        state.roll_over(quota_day());
+        let cost = endpoint.cost();
+        let limit = match priority {
+            Priority::High => self.budget,
+            Priority::Low => self.budget.saturating_sub(self.reserve)
+        };
+        if state.used + cost > limit {
+            return Err(match priority {
+                Priority::High => QuotaError::Exhausted { used: state.used, budget: self.budget },
+                Priority::Low => QuotaError::Deferred { used: state.used, reserve: self.reserve, budget: self.budget }
+            })
+        }
+        state.used += cost;
+        state.run += cost;
+        *state.by_endpoint.entry(endpoint).or_insert(0) += cost;
+        drop(state);
+        self.persist();
+        Ok(())
+    }
+
+    /// Whether `units` can be afforded now without spending.
+    pub fn can_afford(&self, units: u64, priority: Priority) -> bool {
+        let mut state = self.state.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        state.roll_over(quota_day());
+        let limit = match priority {
+            Priority::High => self.budget,
+            Priority::Low => self.budget.saturating_sub(self.reserve)
+        };
+        state.used + units <= limit
+    }
+
+    #[cfg(test)]
+    pub fn used_in_run(&self) -> u64 {
+        self.state.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .run
+    }
+
+    #[cfg(test)]
+    pub fn used_today(&self) -> u64 {
+        let mut state = self.state.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        state.roll_over(quota_day());
+        state.used
+    }
+
+    pub fn report(&self) {
+        let state = self.state.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let breakdown = state.by_endpoint.iter()
+            .map(|(endpoint, used)| format!("{}={}", endpoint.as_str(), used))
+            .collect::<Vec<_>>()
+            .join(", ");
+        info!(quota_run = state.run, quota_today = state.used, quota_budget = self.budget,
+            "quota used: run {} / today {} of {} ({})", state.run, state.used, self.budget, breakdown);
+    }
+
+    /// Schedule a write of the current state, unless one is scheduled and not started yet.
+    fn persist(&self) {
+        if self.pending.swap(true, Ordering::AcqRel) {
+            return
+        }
+        let (path, metrics, budget) = (self.path.clone(), self.metrics.clone(), self.budget);
+        let (state, pending, writer) = (Arc::clone(&self.state), Arc::clone(&self.pending), Arc::clone(&self.writer));
+        let write = move || {
+            // writes are serialized and snapshot after taking turn, so that the last write is the newest.
+            let _turn = writer.lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            pending.store(false, Ordering::Release);
+            let snapshot = state.lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .clone();
+            if let Err(reason) = write_ledger(&path, metrics.as_deref(), budget, &snapshot) {
+                warn!("cannot persist quota ledger: {}", reason);
+            }
+        };
+        write_blocking(write);
+    }
+}
+
+fn write_ledger(path: &Path, metrics: Option<&Path>, budget: u64, state: &LedgerState) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, serde_json::to_vec(state)?)?;
+    if let Some(metrics) = metrics {
+        write_metrics(metrics, budget, state)?;
+    }
+    Ok(())
+}
+
+fn write_metrics(path: &Path, budget: u64, state: &LedgerState) -> std::io::Result<()> {
+    let mut text = String::new();
+    let _ = writeln!(text, "# HELP salmon_quota_units_today YouTube Data API quota units used in the current Pacific-time day.");
+    let _ = writeln!(text, "# TYPE salmon_quota_units_today gauge");
+    for (endpoint, used) in state.by_endpoint.iter() {
+        let _ = writeln!(text, "salmon_quota_units_today{{endpoint=\"{}\"}} {}", endpoint.as_str(), used);
+    }
+    let _ = writeln!(text, "# HELP salmon_quota_units_run YouTube Data API quota units used in the current run.");
+    let _ = writeln!(text, "# TYPE salmon_quota_units_run gauge");
+    let _ = writeln!(text, "salmon_quota_units_run {}", state.run);
+    let _ = writeln!(text, "# HELP salmon_quota_daily_budget Configured daily quota budget.");
+    let _ = writeln!(text, "# TYPE salmon_quota_daily_budget gauge");
+    let _ = writeln!(text, "salmon_quota_daily_budget {}", budget);
+
+    // write then rename, so that collectors never read a half written file.
+    let mut temporary = path.as_os_str().to_os_string();
+    temporary.push(".tmp");
+    std::fs::File::create(&temporary)?.write_all(text.as_bytes())?;
+    std::fs::rename(temporary, path)
+}
+
+#[cfg(test)]
+mod quota_test {
+    use crate::entry::quota::{Endpoint, Priority, QuotaError, QuotaLedger};
+
+    #[test]
+    fn budget_test() {
+        let path = std::env::temp_dir().join(format!("salmon_quota_test_{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let ledger = QuotaLedger::load(&path, 250, 100, None);
+
+        assert!(ledger.try_spend(Endpoint::Search, Priority::Low).is_ok());
+        assert!(matches!(ledger.try_spend(Endpoint::Search, Priority::Low), Err(QuotaError::Deferred { .. })));
+        assert!(ledger.try_spend(Endpoint::Search, Priority::High).is_ok());
+        assert!(ledger.try_spend(Endpoint::Channels, Priority::High).is_ok());
+        assert!(matches!(ledger.try_spend(Endpoint::Search, Priority::High), Err(QuotaError::Exhausted { .. })));
+        assert_eq!(ledger.used_in_run(), 201);
+
+        // persisted daily count is carried over, but not the run count.
+        let reloaded = QuotaLedger::load(&path, 250, 100, None);
+        assert_eq!(reloaded.used_today(), 201);
+        assert_eq!(reloaded.used_in_run(), 0);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn persist_in_runtime_test() {
+        let path = std::env::temp_dir().join(format!("salmon_quota_runtime_test_{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let ledger = QuotaLedger::load(&path, 1000, 0, None);
+        for _ in 0..50 {
+            assert!(ledger.try_spend(Endpoint::Videos, Priority::High).is_ok());
+        }
+
+        // written on a blocking thread, the last write carries every charge.
+        let mut persisted = 0;
+        for _ in 0..100 {
+            persisted = QuotaLedger::load(&path, 1000, 0, None).used_today();
+            if persisted == 50 {
+                break
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(persisted, 50);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
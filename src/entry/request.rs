@@ -5,25 +5,34 @@ use std::collections::vec_deque::VecDeque;
 use std::fmt::Formatter;
 use std::marker::PhantomData;
 use std::str::FromStr;
+use std::sync::Arc;
 use anyhow::{Result, Context};
 use chrono::{Datelike, DateTime, Local};
 use futures::StreamExt;
-use misery_rs::{CacheWrapper, MiseryHandler};
 use once_cell::sync::OnceCell;
 use reqwest::{Client, StatusCode};
 use reqwest::header::{HeaderName, HeaderValue};
 use serde::{Serialize, Deserialize, Deserializer};
 use serde::de::{Error, Visitor};
-use crate::ids::StringId;
-use crate::logger::Logger;
-use crate::models::{Channel, LiverEntry};
-
-fn get_api_key_param() -> &'static str {
-    static API_KEY: OnceCell<String> = OnceCell::new();
-    API_KEY.get_or_init(|| {
-        dotenv::var("API_KEY")
-            .expect("API_KEY is not set.")
-    })
+use tracing::{debug, error, info, info_span, Instrument, warn};
+use crate::entry::api::{self, YoutubeApiError};
+use crate::entry::cache::{Cache, Key, open_cache};
+use crate::entry::cassette::{Cassette, get_cassette};
+use crate::entry::feed::fetch_feed;
+use crate::entry::keys::{get_api_key_pool, KeyPoolError};
+use crate::entry::lifecycle::LiveState;
+use crate::entry::quota::{Endpoint, get_quota_ledger, Priority};
+use crate::entry::summary::RunSummary;
+use crate::ids::{IdFormat, IdFormatError, NumId, StringId, youtube};
+use crate::models::{Channel, Handle, LiverEntry};
+
+/// Replayed responses are served without key and quota, so that a cassette is replayed offline without a key.
+fn is_replaying() -> bool {
+    get_cassette().map(Cassette::is_replaying).unwrap_or(false)
+}
+
+fn has_api_key() -> bool {
+    is_replaying() || !get_api_key_pool().is_empty()
 }
 
 fn get_process_concurrency() -> &'static usize {
@@ -36,208 +45,634 @@ fn get_process_concurrency() -> &'static usize {
     })
 }
 
-fn get_http_client() -> &'static reqwest::Client {
+fn get_discovery_strategy() -> &'static DiscoveryStrategy {
+    static STRATEGY: OnceCell<DiscoveryStrategy> = OnceCell::new();
+    STRATEGY.get_or_init(|| {
+        dotenv::var("DISCOVERY_STRATEGY")
+            .ok()
+            .and_then(|f| f.parse().ok())
+            .unwrap_or(DiscoveryStrategy::Search)
+    })
+}
+
+fn get_playlist_discovery_depth() -> &'static usize {
+    static DEPTH: OnceCell<usize> = OnceCell::new();
+    DEPTH.get_or_init(|| {
+        dotenv::var("PLAYLIST_DISCOVERY_DEPTH")
+            .ok()
+            .and_then(|f| f.parse().ok())
+            .map(|depth: usize| depth.clamp(1, 50))
+            .unwrap_or(50)
+    })
+}
+
+/// Pages followed by `nextPageToken` per channel in search and playlist discovery.
+fn get_discovery_max_pages() -> &'static usize {
+    static PAGES: OnceCell<usize> = OnceCell::new();
+    PAGES.get_or_init(|| {
+        dotenv::var("DISCOVERY_MAX_PAGES")
+            .ok()
+            .and_then(|f| f.parse().ok())
+            .map(|pages: usize| pages.max(1))
+            .unwrap_or(5)
+    })
+}
+
+fn get_handle_resolve_ttl() -> &'static chrono::Duration {
+    static TTL: OnceCell<chrono::Duration> = OnceCell::new();
+    TTL.get_or_init(|| {
+        dotenv::var("HANDLE_RESOLVE_TTL_HOURS")
+            .ok()
+            .and_then(|f| f.parse().ok())
+            .map(chrono::Duration::hours)
+            .unwrap_or_else(|| chrono::Duration::days(7))
+    })
+}
+
+/// Base url of YouTube Data API, configurable by `YOUTUBE_API_BASE_URL` to point at a mock server.
+fn get_api_base_url() -> &'static str {
+    static BASE: OnceCell<String> = OnceCell::new();
+    BASE.get_or_init(|| {
+        dotenv::var("YOUTUBE_API_BASE_URL")
+            .map(|base| base.trim_end_matches('/').to_string())
+            .unwrap_or_else(|_| String::from("https://www.googleapis.com/youtube/v3"))
+    })
+}
+
+fn api_url(resource: &str) -> String {
+    format!("{}/{}", get_api_base_url(), resource)
+}
+
+fn get_request_timeout() -> &'static std::time::Duration {
+    static TIMEOUT: OnceCell<std::time::Duration> = OnceCell::new();
+    TIMEOUT.get_or_init(|| {
+        dotenv::var("REQUEST_TIMEOUT_SECS")
+            .ok()
+            .and_then(|f| f.parse().ok())
+            .map(std::time::Duration::from_secs)
+            .unwrap_or_else(|| std::time::Duration::from_secs(30))
+    })
+}
+
+pub(super) fn get_http_client() -> &'static reqwest::Client {
     static CLIENT: OnceCell<reqwest::Client> = OnceCell::new();
     CLIENT.get_or_init(|| {
-        Client::new()
+        Client::builder()
+            .timeout(*get_request_timeout())
+            .build()
+            .expect("cannot build http client")
     })
 }
 
-pub(super) async fn request_video_info_concurrency(queue: &HashSet<LiverEntry>) -> Result<HashSet<VideoInfo>> {
-    let logger = Logger::new(Some("search api"));
-    let caching: MiseryHandler<StringId<Channel>, Etag> = MiseryHandler::load_from_blocking("./.cache/video_search_cache.json");
-    let client = get_http_client();
-
-    let youtube_ext = queue.iter()
+fn youtube_channels(queue: &HashSet<LiverEntry>) -> Vec<(NumId<LiverEntry>, StringId<Channel>)> {
+    queue.iter()
         .flat_map(|entity| entity.as_ref_site().iter()
             .flat_map(Channel::as_youtube_id)
-            .collect::<Vec<StringId<Channel>>>())
-        .collect::<Vec<StringId<Channel>>>();
+            .map(|id| (entity.breach_extraction_id(), id))
+            .collect::<Vec<(NumId<LiverEntry>, StringId<Channel>)>>())
+        .collect::<Vec<(NumId<LiverEntry>, StringId<Channel>)>>()
+}
+
+/// Upcoming and live videos of the livers. Etags of the `videos` batches are kept under `etag_scope`.
+///
+/// Fails with [`KeyPoolError`] when no API key is set or all of them are exhausted,
+/// every strategy looks up the found videos by `videos` so that feed discovery can not do without a key either.
+#[tracing::instrument(name = "search api", skip_all)]
+pub(super) async fn request_video_info_concurrency(queue: &HashSet<LiverEntry>, etag_scope: &str, summary: &mut RunSummary) -> Result<HashSet<VideoInfo>, YoutubeApiError> {
+    if !is_replaying() {
+        get_api_key_pool().acquire()?;
+    }
+    let youtube_ext = youtube_channels(queue);
+    summary.requested(youtube_ext.len());
+
+    let strategy = match *get_discovery_strategy() {
+        strategy @ (DiscoveryStrategy::Search | DiscoveryStrategy::UploadsPlaylist)
+            if !is_replaying() && !get_quota_ledger().can_afford(strategy.cost() * youtube_ext.len() as u64, Priority::High) => {
+            warn!("quota is near exhaustion, fallback {:?} to feed discovery.", strategy);
+            DiscoveryStrategy::Feed
+        },
+        strategy => strategy
+    };
+    info!(strategy = ?strategy, "discover {} channels", youtube_ext.len());
+
+    let response = match strategy {
+        DiscoveryStrategy::Search => search_upcoming_video_ids(youtube_ext, summary).await,
+        DiscoveryStrategy::UploadsPlaylist => uploads_playlist_video_ids(youtube_ext, summary).await,
+        DiscoveryStrategy::Feed => feed_video_ids(youtube_ext).await
+    };
+
+    info!("finished id search.");
+
+    for picked in 0..((response.len() / 5) + (if (response.len() % 5) > 0 { 1 } else { 0 } )) {
+        let aggregate = response.iter()
+            .skip(5 * picked).take(5)
+            .map(|id| id.as_ref())
+            .collect::<Vec<&str>>()
+            .join(", ");
+        debug!("({:<2}):: {}", picked + 1, aggregate);
+    }
+
+    let aggregates = request_video_details(response, Some(etag_scope), summary).await?.into_iter()
+        .filter(VideoInfo::is_upcoming_or_live)
+        .collect::<HashSet<VideoInfo>>();
+
+    Ok(aggregates)
+}
+
+/// Add `If-None-Match` with the cached etag, unless there is none.
+///
+/// Caches are files which can be edited by hand, so an etag which is not a valid header value is skipped with a warning.
+fn if_none_match(request: reqwest::RequestBuilder, etag: Option<&Etag>) -> reqwest::RequestBuilder {
+    match etag.filter(|etag| !etag.as_ref().is_empty()) {
+        None => request,
+        Some(etag) => match HeaderValue::from_str(etag.as_ref()) {
+            Ok(value) => request.header(HeaderName::from_static("if-none-match"), value),
+            Err(_) => {
+                warn!("cached etag {:?} is not a valid header value, sent without it.", etag.as_ref());
+                request
+            }
+        }
+    }
+}
+
+/// Response with `nextPageToken`.
+trait Paginated: serde::de::DeserializeOwned {
+    fn as_ref_etag(&self) -> &str;
+    fn as_ref_next_page_token(&self) -> Option<&str>;
+}
+
+/// Request pages by following `nextPageToken`, up to `DISCOVERY_MAX_PAGES` (default: 5) pages.
+///
+/// Each page is sent by [`api::send`], so every extra page is charged to the quota ledger.
+/// `etag` is sent with the first page, and `None` is returned when it is answered with `304 Not Modified`.
+/// The etag of the first page does not cover the later ones, so it should be cached only for a single page.
+async fn request_pages<P, F>(endpoint: Endpoint, etag: Option<&Etag>, request: F) -> Result<Option<Vec<P>>, YoutubeApiError>
+    where P: Paginated, F: Fn(&str) -> reqwest::RequestBuilder
+{
+    let mut pages: Vec<P> = Vec::new();
+    let mut token: Option<String> = None;
+    while pages.len() < *get_discovery_max_pages() {
+        let res = api::send(endpoint, Priority::High, |key| match token.as_deref() {
+            Some(token) => request(key).query(&[("pageToken", token)]),
+            None => if_none_match(request(key), etag)
+        }).await?;
+        if res.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None)
+        }
+        let page = res.json::<P>().await?;
+        token = page.as_ref_next_page_token().map(ToString::to_string);
+        pages.push(page);
+        if token.is_none() {
+            return Ok(Some(pages))
+        }
+    }
+    warn!("more than {} pages of {}, the rest are skipped.", pages.len(), endpoint.as_str());
+    Ok(Some(pages))
+}
+
+const SEARCH_PART: &str = "snippet";
+const SEARCH_FIELDS: &str = "(etag, nextPageToken, items(id(videoId)))";
+const VIDEOS_PART: &str = "liveStreamingDetails, statistics, snippet";
+const VIDEOS_FIELDS: &str = "(etag, items(id, snippet(title, description, channelTitle, channelId, publishedAt), statistics(viewCount, likeCount, favoriteCount, commentCount), liveStreamingDetails(actualStartTime, actualEndTime, scheduledStartTime, concurrentViewers, activeLiveChatId)))";
+const CHANNELS_PART: &str = "snippet,statistics";
+const CHANNELS_FIELDS: &str = "(etag, items(id, (snippet(title, description, publishedAt, thumbnails(high(url))))))";
+
+/// Open an etag cache, and drop its etags if they were cached with another `part` or `fields`.
+///
+/// An etag identifies the projected response, so a stale one is answered with `304` for fields we never stored.
+async fn open_etag_cache<K: Key>(name: &str, part: &str, fields: &str) -> Arc<dyn Cache<K, Etag>> {
+    let caching = open_cache::<K, Etag>(name);
+    let fingerprints = open_cache::<String, String>("etag_fingerprints");
+    invalidate_stale_etags(caching.as_ref(), fingerprints.as_ref(), name, &format!("part={}&fields={}", part, fields)).await;
+    caching
+}
+
+/// Etags of every etag cache, as `(cache name, key, etag)`.
+///
+/// Keys of `video_detail_cache` are `{scope}:{joined ids of a videos batch}`.
+pub(super) async fn cached_etags() -> Vec<(&'static str, String, String)> {
+    async fn dump<K: Key + std::fmt::Display>(name: &'static str) -> Vec<(&'static str, String, String)> {
+        open_cache::<K, Etag>(name).all_items().await.into_iter()
+            .map(|(key, etag)| (name, key.to_string(), etag.0))
+            .collect()
+    }
+    let mut etags = dump::<StringId<Channel>>("video_search_cache").await;
+    etags.extend(dump::<String>("video_detail_cache").await);
+    etags.extend(dump::<StringId<Channel>>("ch_search_cache").await);
+    etags
+}
+
+/// Remove every etag, and return how many were removed. The next requests download full responses.
+pub(super) async fn purge_etags() -> usize {
+    async fn purge<K: Key>(name: &str) -> usize {
+        let caching = open_cache::<K, Etag>(name);
+        let keys = caching.all_items().await.into_iter()
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        caching.remove(&keys).await;
+        keys.len()
+    }
+    purge::<StringId<Channel>>("video_search_cache").await
+        + purge::<String>("video_detail_cache").await
+        + purge::<StringId<Channel>>("ch_search_cache").await
+}
+
+/// Remove every etag of `caching` unless `fingerprint` is the recorded one, and return how many were removed.
+async fn invalidate_stale_etags<K: Key>(caching: &dyn Cache<K, Etag>, fingerprints: &dyn Cache<String, String>, name: &str, fingerprint: &str) -> usize {
+    let name = name.to_string();
+    if fingerprints.find_value(&name).await.as_deref() == Some(fingerprint) {
+        return 0
+    }
+    let stale = caching.all_items().await.into_iter()
+        .map(|(key, _)| key)
+        .collect::<Vec<_>>();
+    if !stale.is_empty() {
+        info!("part or fields of {} has changed, {} etags are dropped.", name, stale.len());
+        caching.remove(&stale).await;
+    }
+    fingerprints.put(name, fingerprint.to_string()).await;
+    stale.len()
+}
+
+/// `search?eventType=upcoming`, costs 100 units per page.
+async fn search_upcoming_video_ids(youtube_ext: Vec<(NumId<LiverEntry>, StringId<Channel>)>, summary: &mut RunSummary) -> VecDeque<StringId<VideoInfo>> {
+    let caching = open_etag_cache::<StringId<Channel>>("video_search_cache", SEARCH_PART, SEARCH_FIELDS).await;
+    let client = get_http_client();
 
     // SIDE EFFECT IN ITER MAP !
     // This is incorrect because side effects are prohibited in Monad's fmap.
     // But I couldn't figure out any other way to do it well, so here...
     let responses = futures::stream::iter(youtube_ext)
-        .map(|id| {
+        .map(|(liver, id)| {
             let client = client;
             let caching = &caching;
+            let span = info_span!("search api", liver = %liver, channel = %id);
             async move {
-                let etag = caching.find_value(&id).await.unwrap_or_default();
-                let res = client.get("https://www.googleapis.com/youtube/v3/search")
-                    .header(HeaderName::from_static("if-none-match"), HeaderValue::from_str(etag.as_ref()).expect(""))
+                let etag = caching.find_value(&id).await;
+                debug!("req >> {}", id.as_ref());
+                let res = request_pages::<SearchedObjects, _>(Endpoint::Search, etag.as_ref(), |key| client.get(api_url("search"))
                     .header(HeaderName::from_static("user-agent"), HeaderValue::from_static("Nekomata-salmon (retrieve for scheduled live of virtual liver. [https://github.com/ReiRokusanami0010/salmon])"))
-                    .query(&[("channelId", id.as_ref()), ("part", "snippet"), ("type", "video"),
-                        ("eventType", "upcoming"), ("fields", "(etag, items(id(videoId)))"), ("key", get_api_key_param())])
-                    .send().await
-                    .context(RequestError::HttpGet)
-                    .expect("http_get");
+                    .query(&[("channelId", id.as_ref()), ("part", SEARCH_PART), ("type", "video"), ("eventType", "upcoming"), ("maxResults", "50"),
+                        ("fields", SEARCH_FIELDS), ("key", key)])).await;
                 (res, id)
-            }
+            }.instrument(span)
         }).buffer_unordered(*get_process_concurrency())
-        .inspect(|res| logger.info(format!("req >> {}", res.1.as_ref())))
-        .collect::<Vec<(reqwest::Response, StringId<Channel>)>>().await;
+        .collect::<Vec<(Result<Option<Vec<SearchedObjects>>, YoutubeApiError>, StringId<Channel>)>>().await;
 
     let mut id_queue = VecDeque::new();
-    for response in responses {
-        let parsed: Option<SearchedObjects> = match response.0.status() {
-            StatusCode::OK => {
-                let parsed = response.0.json::<SearchedObjects>().await
-                    .expect("failed parse");
-                logger.info(format!("rec <- {}", response.1.as_ref()));
-                caching.abs(CacheWrapper::new(response.1, Etag::new(&parsed.etag))).await;
-                Some(parsed)
-            },
-            StatusCode::NOT_MODIFIED => {
-                logger.info(format!("___ -- {}", response.1.as_ref()));
-                None
+    for (res, id) in responses {
+        match res {
+            Ok(None) => debug!(channel = %id, "___ -- {}", id.as_ref()),
+            Ok(Some(pages)) => {
+                debug!(channel = %id, "rec <- {} ({} pages)", id.as_ref(), pages.len());
+                // changes on the later pages are not detected by the etag of the first one.
+                match &pages[..] {
+                    [single] => caching.put(id, Etag::new(single.as_ref_etag())).await,
+                    _ => caching.remove(&[id]).await
+                }
+                id_queue.extend(pages);
             },
-            StatusCode::TOO_MANY_REQUESTS => {
-                logger.error("Resource Exhausted, Quota Limit exceeded!");
-                panic!()
-            },
-            _ => {
-                println!("{}", response.0.text().await.expect(""));
-                unimplemented!("unknown error code.")
-            }
-        };
-        id_queue.push_back(parsed);
+            Err(reason) => summary.fail(id, reason)
+        }
     }
 
-    logger.info("finished id search.");
-
-    let response = id_queue.into_iter().flatten()
+    id_queue.into_iter()
         .flat_map(|raw_object| raw_object.items.into_iter()
             .map(|item| item.id.video_id)
             .collect::<Vec<StringId<VideoInfo>>>())
-        .collect::<VecDeque<StringId<VideoInfo>>>();
+        .collect::<VecDeque<StringId<VideoInfo>>>()
+}
 
-    for picked in 0..((response.len() / 5) + (if (response.len() % 5) > 0 { 1 } else { 0 } )) {
-        let aggregate = response.iter()
-            .skip(5 * picked).take(5)
-            .map(|id| id.as_ref())
-            .collect::<Vec<&str>>()
-            .join(", ");
-        logger.info(format!("({:<2}):: {}", picked + 1, aggregate));
+/// `playlistItems` of each channel's uploads playlist, costs 1 unit per page.
+///
+/// Returns every recent upload, upcoming and live items are picked out by the `videos` details.
+/// A page holds `PLAYLIST_DISCOVERY_DEPTH` (default: 50) items.
+async fn uploads_playlist_video_ids(youtube_ext: Vec<(NumId<LiverEntry>, StringId<Channel>)>, summary: &mut RunSummary) -> VecDeque<StringId<VideoInfo>> {
+    let client = get_http_client();
+    let depth = get_playlist_discovery_depth().to_string();
+
+    let responses = futures::stream::iter(youtube_ext)
+        .map(|(liver, id)| {
+            let depth = &depth;
+            let playlist = id.uploads_playlist();
+            let span = info_span!("search api", liver = %liver, channel = %id, playlist = %playlist);
+            async move {
+                debug!("req >> {}", playlist.as_ref());
+                let res = request_pages::<PlaylistItemObjects, _>(Endpoint::PlaylistItems, None, |key| client.get(api_url("playlistItems"))
+                    .header(HeaderName::from_static("user-agent"), HeaderValue::from_static("Nekomata-salmon (retrieve for scheduled live of virtual liver. [https://github.com/ReiRokusanami0010/salmon])"))
+                    .query(&[("playlistId", playlist.as_ref()), ("part", "contentDetails"), ("maxResults", depth.as_str()),
+                        ("fields", "(etag, nextPageToken, items(contentDetails(videoId)))"), ("key", key)])).await;
+                (res, id)
+            }.instrument(span)
+        }).buffer_unordered(*get_process_concurrency())
+        .collect::<Vec<(Result<Option<Vec<PlaylistItemObjects>>, YoutubeApiError>, StringId<Channel>)>>().await;
+
+    let mut id_queue = VecDeque::new();
+    for (res, id) in responses {
+        match res {
+            Ok(pages) => {
+                let pages = pages.unwrap_or_default();
+                debug!(channel = %id, "rec <- {} ({} pages)", id.as_ref(), pages.len());
+                id_queue.extend(pages);
+            },
+            Err(reason) => summary.fail(id, reason)
+        }
     }
 
-    let mut queue: VecDeque<String> = VecDeque::new();
+    id_queue.into_iter()
+        .flat_map(|raw_object| raw_object.items.into_iter()
+            .map(|item| item.content_details.video_id)
+            .collect::<Vec<StringId<VideoInfo>>>())
+        .collect::<VecDeque<StringId<VideoInfo>>>()
+}
 
-    for picked in 0..(response.len() / 50 + (if (response.len() % 50) > 0 { 1 } else { 0 } )) {
-        queue.push_back(response.clone().into_iter()
-            .skip(50 * picked).take(50)
-            .map(|id| id.breach_inner())
-            .collect::<Vec<String>>()
-            .join(","));
+/// `feeds/videos.xml` of each channel, costs no quota.
+///
+/// Returns the latest 15 uploads, upcoming and live items are picked out by the `videos` details.
+async fn feed_video_ids(youtube_ext: Vec<(NumId<LiverEntry>, StringId<Channel>)>) -> VecDeque<StringId<VideoInfo>> {
+    let client = get_http_client();
+
+    futures::stream::iter(youtube_ext)
+        .map(|(liver, id)| {
+            let span = info_span!("search api", liver = %liver, channel = %id);
+            async move {
+                debug!("req >> {}", id.as_ref());
+                match fetch_feed(client, &id).await {
+                    Ok(entries) => entries.into_iter()
+                        .map(|entry| entry.as_ref_video_id().to_owned())
+                        .collect::<Vec<StringId<VideoInfo>>>(),
+                    Err(reason) => {
+                        warn!("cannot fetch feed: {:?}", reason);
+                        Vec::new()
+                    }
+                }
+            }.instrument(span)
+        }).buffer_unordered(*get_process_concurrency())
+        .flat_map(futures::stream::iter)
+        .collect::<VecDeque<StringId<VideoInfo>>>().await
+}
+
+/// `videos` with 50 ids per request, costs 1 unit per request. Batches are sent in parallel under the scheduler.
+///
+/// Ids are sorted before split into batches, so that the same set of ids always makes the same batches.
+/// With `etag_scope`, the etag of each batch is cached by the scope and its ids,
+/// and etags of the scope that no longer match any of the batches are dropped,
+/// so that each caller keeps only the etags of its latest lookup.
+/// A batch answered with `304 Not Modified` has not changed since the last lookup of the same scope,
+/// so its videos are left out of the result, and will not be pushed again.
+/// Without `etag_scope`, every batch is downloaded.
+pub(super) async fn request_video_details(response: VecDeque<StringId<VideoInfo>>, etag_scope: Option<&str>, summary: &mut RunSummary) -> Result<HashSet<VideoInfo>, YoutubeApiError> {
+    request_video_details_with_missing(response, etag_scope, summary).await
+        .map(|(videos, _)| videos)
+}
+
+/// Key of the etag of a `videos` batch in `video_detail_cache`.
+fn detail_etag_key(etag_scope: &str, batch: &str) -> String {
+    format!("{}:{}", etag_scope, batch)
+}
+
+/// Same as [`request_video_details`], with ids that were not returned by the answered batches.
+///
+/// Deleted and private videos are silently left out of `videos` responses.
+/// The etag of a batch with missing ids is not cached, so that they are reported again on the next lookup.
+/// Fails with [`KeyPoolError::Empty`] when no API key is set.
+pub(super) async fn request_video_details_with_missing(response: VecDeque<StringId<VideoInfo>>, etag_scope: Option<&str>, summary: &mut RunSummary) -> Result<(HashSet<VideoInfo>, HashSet<StringId<VideoInfo>>), YoutubeApiError> {
+    if !has_api_key() {
+        return Err(KeyPoolError::Empty.into())
+    }
+    let client = get_http_client();
+    let caching = open_etag_cache::<String>("video_detail_cache", VIDEOS_PART, VIDEOS_FIELDS).await;
+
+    let mut ids = response.into_iter()
+        .map(|id| id.breach_inner())
+        .collect::<Vec<String>>();
+    ids.sort();
+    ids.dedup();
+    let queue = ids.chunks(50)
+        .map(|batch| batch.join(","))
+        .collect::<VecDeque<String>>();
+    if let Some(scope) = etag_scope {
+        let current = queue.iter()
+            .map(|batch| detail_etag_key(scope, batch))
+            .collect::<HashSet<String>>();
+        let prefix = detail_etag_key(scope, "");
+        let stale = caching.all_items().await.into_iter()
+            .map(|(key, _)| key)
+            .filter(|key| key.starts_with(&prefix) && !current.contains(key))
+            .collect::<Vec<_>>();
+        if !stale.is_empty() {
+            caching.remove(&stale).await;
+        }
     }
 
-    let mut response = VecDeque::new();
+    info!("search details");
+    let responses = futures::stream::iter(queue)
+        .map(|video_id| {
+            let caching = &caching;
+            async move {
+                let etag = match etag_scope {
+                    Some(scope) => caching.find_value(&detail_etag_key(scope, &video_id)).await,
+                    None => None
+                };
+                let external = api::send(Endpoint::Videos, Priority::High, |key| if_none_match(client.get(api_url("videos")), etag.as_ref())
+                    .header(HeaderName::from_static("user-agent"), HeaderValue::from_static("Nekomata-salmon (retrieve for scheduled live of virtual liver. [https://github.com/ReiRokusanami0010/salmon])"))
+                    .query(&[("id", video_id.as_str()), ("part", VIDEOS_PART), ("fields", VIDEOS_FIELDS), ("key", key)])).await;
+                let parsed = match external {
+                    Ok(external) if external.status() == StatusCode::NOT_MODIFIED => Ok(None),
+                    Ok(external) => external.json::<SearchedVideoInfoObjects>().await
+                        .map(Some)
+                        .map_err(YoutubeApiError::from),
+                    Err(reason) => Err(reason)
+                };
+                (parsed, video_id)
+            }
+        }).buffer_unordered(*get_process_concurrency())
+        .collect::<Vec<(Result<Option<SearchedVideoInfoObjects>, YoutubeApiError>, String)>>().await;
 
-    logger.info("search details");
-    for video_id in queue {
-        let external = client.get("https://www.googleapis.com/youtube/v3/videos")
-            .header(HeaderName::from_static("user-agent"), HeaderValue::from_static("Nekomata-salmon (retrieve for scheduled live of virtual liver. [https://github.com/ReiRokusanami0010/salmon])"))
-            .query(&[("id", video_id.as_str()), ("part", "liveStreamingDetails, statistics, snippet"),
-                ("fields", "(etag, items(id, snippet(title, description, channelTitle, channelId, publishedAt), statistics, liveStreamingDetails))"),
-                ("key", get_api_key_param())])
-            .send()
-            .await
-            .context(RequestError::HttpGet)?;
-        let parsed: Option<SearchedVideoInfoObjects> = match external.status() {
-            StatusCode::OK => {
-                let parsed = external.json::<SearchedVideoInfoObjects>().await
-                    .context(RequestError::DataParse)?;
-                Some(parsed)
+    let mut response = VecDeque::new();
+    let mut missing = HashSet::new();
+    let mut exhausted = false;
+    for (parsed, video_id) in responses {
+        match parsed {
+            Ok(None) => debug!("___ -- {}", video_id),
+            Ok(Some(parsed)) => {
+                let returned = parsed.items.iter()
+                    .map(|video| video.as_ref_id().as_ref())
+                    .collect::<HashSet<&str>>();
+                let absent = video_id.split(',')
+                    .filter(|id| !returned.contains(id))
+                    .map(StringId::<VideoInfo>::new)
+                    .collect::<Vec<_>>();
+                if let Some(scope) = etag_scope.filter(|_| absent.is_empty()) {
+                    caching.put(detail_etag_key(scope, &video_id), Etag::new(&parsed.etag)).await;
+                }
+                missing.extend(absent);
+                response.push_back(parsed)
             },
-            StatusCode::NOT_MODIFIED => None,
-            StatusCode::TOO_MANY_REQUESTS => {
-                logger.error("Resource Exhausted, Quota Limit exceeded!");
-                panic!()
+            // batches sent after exhaustion fail the same way, report once.
+            Err(reason) if reason.is_exhausted() => {
+                if !exhausted {
+                    error!("skip remaining detail search: {}", reason);
+                    summary.fail_details(reason);
+                }
+                exhausted = true
             },
-            _ => {
-                println!("{}", external.text().await.expect(""));
-                unimplemented!("unknown error code.")
-            }
-        };
-        response.push_back(parsed)
+            Err(reason) => summary.fail_details(reason)
+        }
     }
-    logger.info("finished detail search.");
+    info!("finished detail search.");
 
-    let aggregates = response.into_iter().flatten()
+    let aggregates = response.into_iter()
         .flat_map(|searched| searched.items)
         .collect::<HashSet<VideoInfo>>();
 
-    Ok(aggregates)
-
+    Ok((aggregates, missing))
 }
 
-pub(super) async fn channel_info_request(entry: &HashSet<LiverEntry>) -> anyhow::Result<HashSet<ChannelInfo>> {
-    let logger = Logger::new(Some("search api"));
+#[tracing::instrument(name = "search api", skip_all)]
+pub(super) async fn channel_info_request(entry: &HashSet<LiverEntry>, summary: &mut RunSummary) -> anyhow::Result<HashSet<ChannelInfo>> {
     let client = get_http_client();
-    let caching: MiseryHandler<StringId<Channel>, Etag> = MiseryHandler::load_from_blocking("./.cache/ch_search_cache.json");
-    let youtube_ext = entry.iter()
-        .flat_map(|entity| entity.as_ref_site().iter()
-            .flat_map(Channel::as_youtube_id)
-            .collect::<Vec<StringId<Channel>>>())
-        .collect::<Vec<StringId<Channel>>>();
+    let caching = open_etag_cache::<StringId<Channel>>("ch_search_cache", CHANNELS_PART, CHANNELS_FIELDS).await;
+    let youtube_ext = youtube_channels(entry);
+    summary.requested(youtube_ext.len());
 
     // notify: Line 63-65
     let responses = futures::stream::iter(youtube_ext)
-        .map(|id| {
+        .map(|(liver, id)| {
             let client = client;
             let caching = &caching;
+            let span = info_span!("search api", liver = %liver, channel = %id);
             async move {
-                let etag = caching.find_value(&id).await.unwrap_or_default();
-                let res = client.get("https://www.googleapis.com/youtube/v3/channels")
-                    .header(HeaderName::from_static("if-none-match"), HeaderValue::from_str(etag.as_ref()).expect(""))
+                let etag = caching.find_value(&id).await;
+                let res = api::send(Endpoint::Channels, Priority::Low, |key| if_none_match(client.get(api_url("channels")), etag.as_ref())
                     .header(HeaderName::from_static("user-agent"), HeaderValue::from_static("Nekomata-salmon (retrieve for scheduled live of virtual liver. [https://github.com/ReiRokusanami0010/salmon])"))
-                    .query(&[("id", id.as_ref()), ("part", "snippet,statistics"), ("fields", "(etag, items(id, (snippet(title, description, publishedAt, thumbnails(high(url))))))"), ("key", get_api_key_param())])
-                    .send().await
-                    .context(RequestError::HttpGet)
-                    .expect("http_get");
+                    .query(&[("id", id.as_ref()), ("part", CHANNELS_PART), ("fields", CHANNELS_FIELDS), ("key", key)])).await;
                 (res, id)
-            }
+            }.instrument(span)
         }).buffer_unordered(*get_process_concurrency())
         .collect::<VecDeque<_>>().await;
 
     let mut id_queue = VecDeque::new();
-    for response in responses {
-        let parsed: Option<ChannelInfoWithEtag> = match response.0.status() {
-            StatusCode::OK => {
-                let parsed = response.0.json::<ChannelInfoWithEtag>().await
-                    .expect("failed parse");
-                logger.info(format!("rec <- {}", response.1.as_ref()));
-                caching.abs(CacheWrapper::new(response.1, Etag::new(&parsed.etag))).await;
-                Some(parsed)
+    for (res, id) in responses {
+        let parsed = match res {
+            Ok(res) if res.status() == StatusCode::NOT_MODIFIED => {
+                debug!(channel = %id, "___ -- {}", id.as_ref());
+                continue
             },
-            StatusCode::NOT_MODIFIED => {
-                logger.info(format!("___ -- {}", response.1.as_ref()));
-                None
-            },
-            StatusCode::TOO_MANY_REQUESTS => {
-                logger.error("Resource Exhausted, Quota Limit exceeded!");
-                panic!()
-            },
-            _ => {
-                println!("{}", response.0.text().await.expect(""));
-                unimplemented!("unknown error code.")
-            }
+            Ok(res) => res.json::<ChannelInfoWithEtag>().await
+                .map_err(YoutubeApiError::from),
+            Err(reason) => Err(reason)
         };
-        id_queue.push_back(parsed);
+        match parsed {
+            Ok(parsed) => {
+                debug!(channel = %id, "rec <- {}", id.as_ref());
+                caching.put(id, Etag::new(&parsed.etag)).await;
+                id_queue.push_back(parsed);
+            },
+            Err(reason) => summary.fail(id, reason)
+        }
     }
 
-    let response = id_queue.into_iter().flatten()
+    let response = id_queue.into_iter()
         .flat_map(|raw_object| raw_object.separate_etag().1)
         .collect::<HashSet<ChannelInfo>>();
 
     Ok(response)
 }
 
+/// Fill `id` of every youtube channel that is written only as `@handle`.
+///
+/// Resolved ids are cached for `HANDLE_RESOLVE_TTL_HOURS` (default: 7 days),
+/// so that resolution costs no quota on every run.
+/// Channels that cannot be resolved are left as is and will be skipped.
+#[tracing::instrument(name = "resolve", skip_all, fields(liver = %entry.as_ref_id()))]
+pub(super) async fn resolve_liver_handles(entry: &mut LiverEntry) {
+    if entry.as_ref_site().iter().all(|channel| channel.as_unresolved_youtube_handle().is_none()) {
+        return
+    }
+    let caching = open_cache::<StringId<Handle>, ResolvedHandle>("handle_cache");
+    for channel in entry.as_mut_site().iter_mut() {
+        let handle = match channel.as_unresolved_youtube_handle() {
+            Some(handle) => handle.to_owned(),
+            None => continue
+        };
+        let cached = caching.find_value(&handle).await;
+        if let Some(cached) = cached.as_ref().filter(|cached| !cached.is_expired()) {
+            channel.resolve_youtube_handle(cached.id.to_owned());
+            continue
+        }
+        let resolved = match handle_resolve_request(&handle).await {
+            Ok(resolved) => resolved,
+            Err(reason) => {
+                error!("cannot resolve {}: {:?}", handle, reason);
+                if let Some(cached) = cached {
+                    warn!("use expired resolution {} -> {}", handle, cached.id);
+                    channel.resolve_youtube_handle(cached.id);
+                }
+                continue
+            }
+        };
+        match cached {
+            Some(cached) if cached.id != resolved => warn!("channel id of {} has changed! {} -> {}", handle, cached.id, resolved),
+            Some(_) => (),
+            None => info!("resolved {} -> {}", handle, resolved)
+        }
+        caching.put(handle, ResolvedHandle::new(resolved.to_owned())).await;
+        channel.resolve_youtube_handle(resolved);
+    }
+}
+
+async fn handle_resolve_request(handle: &StringId<Handle>) -> Result<StringId<Channel>> {
+    let res = api::send(Endpoint::Channels, Priority::Low, |key| get_http_client().get(api_url("channels"))
+        .header(HeaderName::from_static("user-agent"), HeaderValue::from_static("Nekomata-salmon (retrieve for scheduled live of virtual liver. [https://github.com/ReiRokusanami0010/salmon])"))
+        .query(&[("forHandle", handle.as_ref()), ("part", "id"), ("fields", "items(id)"), ("key", key)])).await?;
+    res.json::<HandleResolveObjects>().await
+        .context(RequestError::DataParse)?
+        .items.into_iter()
+        .map(|item| item.id)
+        .next()
+        .ok_or_else(|| RequestError::HandleNotFound(handle.to_string()).into())
+}
+
+/// How upcoming lives are discovered, selected by `DISCOVERY_STRATEGY`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum DiscoveryStrategy {
+    /// `search?eventType=upcoming` per channel. (100 units)
+    Search,
+    /// `playlistItems` of uploads playlist per channel. (1 unit)
+    UploadsPlaylist,
+    /// `feeds/videos.xml` per channel. (no quota, details of the found videos still need an API key)
+    Feed
+}
+
+impl DiscoveryStrategy {
+    /// Quota units per channel.
+    pub fn cost(&self) -> u64 {
+        match self {
+            DiscoveryStrategy::Search => Endpoint::Search.cost(),
+            DiscoveryStrategy::UploadsPlaylist => Endpoint::PlaylistItems.cost(),
+            DiscoveryStrategy::Feed => 0
+        }
+    }
+}
+
+impl FromStr for DiscoveryStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "search" => Ok(DiscoveryStrategy::Search),
+            "playlist" | "uploads" => Ok(DiscoveryStrategy::UploadsPlaylist),
+            "feed" | "atom" => Ok(DiscoveryStrategy::Feed),
+            other => Err(format!("unknown discovery strategy: {}", other))
+        }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 enum RequestError {
-    #[error("failed get http request.")]
-    HttpGet,
-    #[error("failed to load etag from cache.")]
-    ETagLoad,
+    #[error("no channel found for {}.", .0)]
+    HandleNotFound(String),
     #[error("cannot parse. this data structure is wrong.")]
     DataParse
 }
@@ -255,6 +690,10 @@ impl VideoInfo {
         &self.details
     }
 
+    pub fn as_ref_statistics(&self) -> &Statistics {
+        &self.statistics
+    }
+
     pub fn as_ref_title(&self) -> &str {
         &self.snippet.title.0
     }
@@ -263,9 +702,18 @@ impl VideoInfo {
         &self.snippet.description.0
     }
 
+    pub fn live_state(&self) -> LiveState {
+        LiveState::from(&self.details)
+    }
+
     pub fn is_live_finished(&self) -> bool {
-        self.details.actual_end_time.is_some()
-            || self.details.scheduled_start_time.unwrap().timestamp() <= Local::now().timestamp()
+        self.live_state() == LiveState::Ended
+    }
+
+    /// Whether this is an upcoming or currently live stream, not a normal upload or an archive.
+    pub fn is_upcoming_or_live(&self) -> bool {
+        self.details.actual_end_time.is_none()
+            && (self.details.scheduled_start_time.is_some() || self.details.actual_start_time.is_some())
     }
 
     pub fn is_too_long_span_live(&self) -> bool {
@@ -276,6 +724,15 @@ impl VideoInfo {
     }
 }
 
+impl IdFormat for VideoInfo {
+    fn validate(id: &str) -> Result<(), IdFormatError> {
+        if !youtube::is_video_id(id) {
+            return Err(IdFormatError::Video(id.to_string()))
+        }
+        Ok(())
+    }
+}
+
 impl VideoInfoSnippet {
     pub fn as_ref_dependency_channel_id(&self) -> &StringId<Channel> {
         &self.channel_id
@@ -298,6 +755,28 @@ impl LiveStreamingDetails {
     pub fn as_ref_actual_end_time_optional(&self) -> &Option<DateTime<Local>> {
         &self.actual_end_time
     }
+
+    pub fn as_ref_concurrent_viewers_optional(&self) -> &Option<i64> {
+        &self.concurrent_viewers
+    }
+}
+
+impl Statistics {
+    pub fn as_ref_view_count(&self) -> &i64 {
+        &self.view_count
+    }
+
+    pub fn as_ref_like_count(&self) -> &i64 {
+        &self.like_count
+    }
+
+    pub fn as_ref_favorite_count(&self) -> &i64 {
+        &self.favorite_count
+    }
+
+    pub fn as_ref_comment_count(&self) -> &i64 {
+        &self.comment_count
+    }
 }
 
 #[allow(dead_code)]
@@ -315,13 +794,54 @@ impl Etag {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
+struct ResolvedHandle {
+    id: StringId<Channel>,
+    resolved_at: DateTime<Local>
+}
+
+impl ResolvedHandle {
+    fn new(id: StringId<Channel>) -> ResolvedHandle {
+        Self { id, resolved_at: Local::now() }
+    }
+
+    fn is_expired(&self) -> bool {
+        self.resolved_at + *get_handle_resolve_ttl() <= Local::now()
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
+struct HandleResolveObjects {
+    #[serde(default)]
+    items: Vec<HandleResolveItem>
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
+struct HandleResolveItem {
+    id: StringId<Channel>
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
 struct SearchedObjects {
     etag: String,
+    #[serde(rename = "nextPageToken", default)]
+    next_page_token: Option<String>,
     items: Vec<IdentifierWrapper>
 }
 
+impl Paginated for SearchedObjects {
+    fn as_ref_etag(&self) -> &str {
+        &self.etag
+    }
+
+    fn as_ref_next_page_token(&self) -> Option<&str> {
+        self.next_page_token.as_deref()
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
 struct IdentifierWrapper {
@@ -335,6 +855,40 @@ struct Identifier {
     video_id: StringId<VideoInfo>
 }
 
+#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
+struct PlaylistItemObjects {
+    etag: String,
+    #[serde(rename = "nextPageToken", default)]
+    next_page_token: Option<String>,
+    #[serde(default)]
+    items: Vec<PlaylistItem>
+}
+
+impl Paginated for PlaylistItemObjects {
+    fn as_ref_etag(&self) -> &str {
+        &self.etag
+    }
+
+    fn as_ref_next_page_token(&self) -> Option<&str> {
+        self.next_page_token.as_deref()
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
+struct PlaylistItem {
+    #[serde(rename = "contentDetails")]
+    content_details: PlaylistItemContentDetails
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
+struct PlaylistItemContentDetails {
+    #[serde(rename = "videoId")]
+    video_id: StringId<VideoInfo>
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
 struct SearchedVideoInfoObjects {
@@ -348,6 +902,8 @@ pub struct VideoInfo {
     id: StringId<VideoInfo>,
     snippet: VideoInfoSnippet,
     statistics: Statistics,
+    // absent for normal uploads.
+    #[serde(default)]
     #[serde(rename = "liveStreamingDetails")]
     details: LiveStreamingDetails
 }
@@ -389,7 +945,7 @@ pub struct Statistics {
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash, Default)]
 pub struct LiveStreamingDetails {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "actualStartTime")]
@@ -402,7 +958,13 @@ pub struct LiveStreamingDetails {
     scheduled_start_time: Option<DateTime<Local>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "activeLiveChatId")]
-    active_live_chat_id: Option<String>
+    active_live_chat_id: Option<String>,
+    // present only while live, and absent when the owner hides it.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(with = "from_string_optional")]
+    #[serde(rename = "concurrentViewers")]
+    concurrent_viewers: Option<i64>
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
@@ -460,6 +1022,33 @@ mod from_string {
     }
 }
 
+mod from_string_optional {
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    use serde::{de, Serializer, Deserialize, Deserializer};
+
+    pub fn serialize<T, S>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
+        where T: Display,
+              S: Serializer
+    {
+        match value {
+            Some(value) => serializer.collect_str(value),
+            None => serializer.serialize_none()
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+        where T: FromStr,
+              T::Err: Display,
+              D: Deserializer<'de>
+    {
+        Option::<String>::deserialize(deserializer)?
+            .map(|value| value.parse().map_err(de::Error::custom))
+            .transpose()
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
 struct ChannelInfoWithEtag {
@@ -499,6 +1088,29 @@ impl ChannelInfo {
     }
 }
 
+impl IdFormat for ChannelInfo {
+    fn validate(id: &str) -> Result<(), IdFormatError> {
+        Channel::validate(id)
+    }
+}
+
+// `channels` API returns the same id space as the configured `Channel`, checked again by the target format.
+impl TryFrom<StringId<ChannelInfo>> for StringId<Channel> {
+    type Error = IdFormatError;
+
+    fn try_from(id: StringId<ChannelInfo>) -> Result<Self, Self::Error> {
+        StringId::try_new(id.breach_inner())
+    }
+}
+
+impl TryFrom<StringId<Channel>> for StringId<ChannelInfo> {
+    type Error = IdFormatError;
+
+    fn try_from(id: StringId<Channel>) -> Result<Self, Self::Error> {
+        StringId::try_new(id.breach_inner())
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
 pub struct ChannelInfoSnippet {
@@ -544,4 +1156,58 @@ impl ChannelInfoThumbnail {
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
 struct HighRes {
     url: String
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod request_test {
+    use crate::entry::cache::{Cache, JsonCache};
+    use crate::entry::request::{ChannelInfo, Etag, if_none_match, invalidate_stale_etags};
+    use crate::ids::{IdFormatError, StringId};
+    use crate::models::Channel;
+
+    #[test]
+    fn channel_id_conversion_test() {
+        let info = StringId::<ChannelInfo>::new("UCvaTdHTWBGv3MKj3KVqJVCw");
+        let channel = StringId::<Channel>::try_from(info).expect("");
+        assert_eq!(channel.as_ref(), "UCvaTdHTWBGv3MKj3KVqJVCw");
+        assert_eq!(StringId::<ChannelInfo>::try_from(channel).expect("").as_ref(), "UCvaTdHTWBGv3MKj3KVqJVCw");
+        assert_eq!(StringId::<Channel>::try_from(StringId::<ChannelInfo>::new("@nekomataokayu")),
+                   Err(IdFormatError::Channel("@nekomataokayu".to_string())));
+    }
+
+    #[test]
+    fn if_none_match_test() {
+        let client = reqwest::Client::new();
+        let header = |etag: Option<Etag>| if_none_match(client.get("http://127.0.0.1:1/videos"), etag.as_ref())
+            .build().expect("")
+            .headers().get("if-none-match")
+            .map(|value| value.to_str().expect("").to_string());
+        assert_eq!(header(Some(Etag::new("\"abc\""))), Some(String::from("\"abc\"")));
+        assert_eq!(header(None), None);
+        assert_eq!(header(Some(Etag::default())), None);
+        assert_eq!(header(Some(Etag::new("broken\netag"))), None);
+    }
+
+    #[tokio::test]
+    async fn etag_fingerprint_test() {
+        let dir = std::env::temp_dir();
+        let etags = dir.join(format!("salmon_etag_test_{}.json", std::process::id())).to_string_lossy().to_string();
+        let recorded = dir.join(format!("salmon_fingerprint_test_{}.json", std::process::id())).to_string_lossy().to_string();
+        let _ = std::fs::remove_file(&etags);
+        let _ = std::fs::remove_file(&recorded);
+        let caching: JsonCache<String, Etag> = JsonCache::load(etags.as_str());
+        let fingerprints: JsonCache<String, String> = JsonCache::load(recorded.as_str());
+
+        // etags cached before fingerprints were recorded are dropped too.
+        caching.put(String::from("Xq3bQ0nTZMg"), Etag::new("old")).await;
+        assert_eq!(invalidate_stale_etags(&caching, &fingerprints, "video_detail_cache", "part=snippet").await, 1);
+        caching.put(String::from("Xq3bQ0nTZMg"), Etag::new("new")).await;
+        assert_eq!(invalidate_stale_etags(&caching, &fingerprints, "video_detail_cache", "part=snippet").await, 0);
+        assert_eq!(caching.find_value(&String::from("Xq3bQ0nTZMg")).await, Some(Etag::new("new")));
+        assert_eq!(invalidate_stale_etags(&caching, &fingerprints, "video_detail_cache", "part=snippet,statistics").await, 1);
+        assert!(caching.all_items().await.is_empty());
+
+        let _ = std::fs::remove_file(&etags);
+        let _ = std::fs::remove_file(&recorded);
+    }
+}
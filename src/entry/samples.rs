@@ -0,0 +1,243 @@
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Local};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info};
+use crate::entry::{get_or_init_config, video_cache_name};
+use crate::entry::cache::{Cache, JsonCache, open_cache};
+use crate::entry::lifecycle::{LiveState, may_be_live};
+use crate::entry::request::{request_video_details, VideoInfo};
+use crate::entry::summary::RunSummary;
+use crate::ids::StringId;
+
+/// Sampling is enabled by `SAMPLE_INTERVAL_SECS`.
+pub fn get_sample_interval() -> &'static Option<Duration> {
+    static INTERVAL: OnceCell<Option<Duration>> = OnceCell::new();
+    INTERVAL.get_or_init(|| {
+        dotenv::var("SAMPLE_INTERVAL_SECS")
+            .ok()
+            .and_then(|f| f.parse().ok())
+            .filter(|secs: &u64| *secs > 0)
+            .map(Duration::from_secs)
+    })
+}
+
+/// `samples` cache, or a JSON file at `SAMPLE_PATH`.
+pub fn get_sample_store() -> &'static SampleStore {
+    static STORE: OnceCell<SampleStore> = OnceCell::new();
+    STORE.get_or_init(|| match dotenv::var("SAMPLE_PATH") {
+        Ok(path) => SampleStore::load(path),
+        Err(_) => SampleStore::new(open_cache("samples"))
+    })
+}
+
+/// Numbers of a live stream at a point in time.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct Sample {
+    sampled_at: DateTime<Local>,
+    #[serde(default)]
+    concurrent_viewers: Option<i64>,
+    like_count: i64,
+    view_count: i64
+}
+
+impl Sample {
+    pub fn new(sampled_at: DateTime<Local>, video: &VideoInfo) -> Sample {
+        Self {
+            sampled_at,
+            concurrent_viewers: *video.as_ref_live_streaming_details().as_ref_concurrent_viewers_optional(),
+            like_count: *video.as_ref_statistics().as_ref_like_count(),
+            view_count: *video.as_ref_statistics().as_ref_view_count()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Json
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            other => Err(format!("unknown export format: {}", other))
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SampleError {
+    #[error("cannot write samples: {}", .0)]
+    Write(#[from] std::io::Error),
+    #[error("cannot serialize samples: {}", .0)]
+    Serialize(#[from] serde_json::Error)
+}
+
+/// Time series of [`Sample`] per video.
+pub struct SampleStore {
+    caching: Arc<dyn Cache<StringId<VideoInfo>, Vec<Sample>>>
+}
+
+impl SampleStore {
+    pub fn new(caching: Arc<dyn Cache<StringId<VideoInfo>, Vec<Sample>>>) -> SampleStore {
+        Self { caching }
+    }
+
+    /// Store in a JSON file at `path`.
+    pub fn load(path: impl Into<String>) -> SampleStore {
+        Self::new(Arc::new(JsonCache::load(path)))
+    }
+
+    #[cfg(test)]
+    pub async fn record(&self, sample: Sample, video: &StringId<VideoInfo>) {
+        self.record_all(vec![(video.to_owned(), sample)]).await
+    }
+
+    /// Append samples of several videos, written at once.
+    pub async fn record_all(&self, samples: Vec<(StringId<VideoInfo>, Sample)>) {
+        let mut updated = Vec::with_capacity(samples.len());
+        for (video, sample) in samples {
+            let mut series = self.series(&video).await;
+            series.push(sample);
+            updated.push((video, series));
+        }
+        self.caching.put_all(updated).await;
+    }
+
+    pub async fn series(&self, video: &StringId<VideoInfo>) -> Vec<Sample> {
+        self.caching.find_value(video).await.unwrap_or_default()
+    }
+
+    /// Write samples of all videos, or only of `video`, ordered by video and time.
+    ///
+    /// CSV has a header line `video_id,sampled_at,concurrent_viewers,like_count,view_count`,
+    /// and JSON is an array of objects with the same keys.
+    pub async fn export(&self, format: ExportFormat, video: Option<&StringId<VideoInfo>>, mut out: impl Write) -> Result<usize, SampleError> {
+        let mut rows = self.caching.all_items().await.into_iter()
+            .filter(|(id, _)| video.map(|video| id == video).unwrap_or(true))
+            .flat_map(|(id, series)| {
+                let id = id.as_ref().to_string();
+                series.into_iter().map(move |sample| (id.clone(), sample))
+            })
+            .collect::<Vec<(String, Sample)>>();
+        rows.sort_by(|(a, x), (b, y)| a.cmp(b).then(x.sampled_at.cmp(&y.sampled_at)));
+
+        match format {
+            ExportFormat::Csv => {
+                writeln!(out, "video_id,sampled_at,concurrent_viewers,like_count,view_count")?;
+                for (id, sample) in rows.iter() {
+                    writeln!(out, "{},{},{},{},{}", id, sample.sampled_at.to_rfc3339(),
+                        sample.concurrent_viewers.map(|viewers| viewers.to_string()).unwrap_or_default(),
+                        sample.like_count, sample.view_count)?;
+                }
+            },
+            ExportFormat::Json => {
+                #[derive(Serialize)]
+                struct Row<'a> {
+                    video_id: &'a str,
+                    #[serde(flatten)]
+                    sample: &'a Sample
+                }
+                let rows = rows.iter()
+                    .map(|(video_id, sample)| Row { video_id, sample })
+                    .collect::<Vec<_>>();
+                serde_json::to_writer_pretty(&mut out, &rows)?;
+                writeln!(out)?;
+            }
+        }
+        Ok(rows.len())
+    }
+}
+
+/// Sample every cached stream that may be live, every `interval`.
+///
+/// Candidates are the cached videos, kept up to date by the periodic request and WebSub notifications.
+/// Details are downloaded without etags, so that every tick has a sample of each live stream,
+/// and the samples of a tick are written at once.
+pub(super) async fn run(interval: Duration) -> anyhow::Result<()> {
+    info!("sample live streams every {}sec", interval.as_secs());
+    let store = get_sample_store();
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let mut candidates = Vec::new();
+        for aff in get_or_init_config().keys() {
+            let caching = open_cache::<StringId<VideoInfo>, VideoInfo>(&video_cache_name(aff));
+            candidates.extend(caching.all_items().await.into_iter()
+                .map(|(_, video)| video)
+                .filter(may_be_live)
+                .map(|video| video.as_ref_id().to_owned()));
+        }
+        if candidates.is_empty() {
+            debug!("no live stream to sample.");
+            continue
+        }
+
+        let videos = match request_video_details(candidates.into_iter().collect(), None, &mut RunSummary::default()).await {
+            Ok(videos) => videos,
+            Err(reason) => {
+                error!("cannot sample live streams: {:?}", reason);
+                continue
+            }
+        };
+        let sampled_at = Local::now();
+        let samples = videos.iter()
+            .filter(|video| video.live_state() == LiveState::Live)
+            .map(|video| (video.as_ref_id().to_owned(), Sample::new(sampled_at, video)))
+            .collect::<Vec<_>>();
+        let count = samples.len();
+        store.record_all(samples).await;
+        debug!("sampled {} live streams.", count);
+    }
+}
+
+#[cfg(test)]
+mod samples_test {
+    use chrono::{Duration, Local};
+    use crate::entry::request::VideoInfo;
+    use crate::entry::samples::{ExportFormat, Sample, SampleStore};
+    use crate::ids::StringId;
+
+    #[tokio::test]
+    async fn export_test() {
+        let path = std::env::temp_dir().join(format!("salmon_samples_test_{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let video: VideoInfo = serde_json::from_str(&std::fs::read_to_string(".test/youtube/videos/Xq3bQ0nTZMg.json").expect("")).expect("");
+        let id = StringId::<VideoInfo>::new("Xq3bQ0nTZMg");
+
+        let store = SampleStore::load(path.to_string_lossy());
+        let now = Local::now();
+        store.record(Sample::new(now, &video), &id).await;
+        store.record(Sample::new(now - Duration::minutes(1), &video), &id).await;
+
+        // persisted, and ordered by time on export.
+        let store = SampleStore::load(path.to_string_lossy());
+        assert_eq!(store.series(&id).await.len(), 2);
+        let mut csv = Vec::new();
+        assert_eq!(store.export(ExportFormat::Csv, None, &mut csv).await.expect(""), 2);
+        let csv = String::from_utf8(csv).expect("");
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "video_id,sampled_at,concurrent_viewers,like_count,view_count");
+        assert_eq!(lines[1], format!("Xq3bQ0nTZMg,{},1532,120,0", (now - Duration::minutes(1)).to_rfc3339()));
+
+        let mut json = Vec::new();
+        store.export(ExportFormat::Json, Some(&StringId::new("FrEeChAt001")), &mut json).await.expect("");
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&json).expect(""), serde_json::json!([]));
+
+        // samples of a tick are appended at once.
+        let other = StringId::<VideoInfo>::new("pG2wAiTiNg0");
+        store.record_all(vec![(id.clone(), Sample::new(now, &video)), (other.clone(), Sample::new(now, &video))]).await;
+        assert_eq!(store.series(&id).await.len(), 3);
+        assert_eq!(store.series(&other).await.len(), 1);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
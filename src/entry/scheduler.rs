@@ -0,0 +1,235 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::ops::Bound;
+use std::sync::Mutex;
+use std::time::Duration;
+use once_cell::sync::OnceCell;
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+
+tokio::task_local! {
+    static LANE: String;
+}
+
+/// `REQUEST_RATE` requests per second (default: 10) with bursts of `REQUEST_BURST` (default: same as the rate),
+/// and at most `REQUEST_CONCURRENT` (default: 16) requests in flight.
+pub fn get_scheduler() -> &'static Scheduler {
+    static SCHEDULER: OnceCell<Scheduler> = OnceCell::new();
+    SCHEDULER.get_or_init(|| {
+        let rate = dotenv::var("REQUEST_RATE")
+            .ok()
+            .and_then(|f| f.parse().ok())
+            .filter(|rate: &f64| *rate > 0.0)
+            .unwrap_or(10.0);
+        let burst = dotenv::var("REQUEST_BURST")
+            .ok()
+            .and_then(|f| f.parse().ok())
+            .filter(|burst: &f64| *burst >= 1.0)
+            .unwrap_or_else(|| rate.max(1.0));
+        let concurrency = dotenv::var("REQUEST_CONCURRENT")
+            .ok()
+            .and_then(|f| f.parse().ok())
+            .map(|concurrency: usize| concurrency.max(1))
+            .unwrap_or(16);
+        Scheduler::new(rate, burst, concurrency)
+    })
+}
+
+/// Run the future in a lane, requests from different lanes are served in turn.
+pub async fn in_lane<F: Future>(lane: impl Into<String>, fut: F) -> F::Output {
+    LANE.scope(lane.into(), fut).await
+}
+
+fn current_lane() -> String {
+    LANE.try_with(Clone::clone).unwrap_or_default()
+}
+
+/// Shared gate of every request sent to YouTube.
+///
+/// A request first waits for a slot under the concurrency cap, then for a token of the bucket.
+/// While slots are full, waiting requests are queued per lane (an affiliation in the handlers),
+/// and freed slots are handed to the lanes in round-robin order,
+/// so that a large affiliation cannot starve the others.
+#[derive(Debug)]
+pub struct Scheduler {
+    rate: f64,
+    burst: f64,
+    concurrency: usize,
+    bucket: Mutex<Bucket>,
+    slots: Mutex<Slots>
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Goes below zero while reserved by waiting requests.
+    tokens: f64,
+    updated: Instant
+}
+
+#[derive(Debug, Default)]
+struct Slots {
+    in_flight: usize,
+    waiting: BTreeMap<String, VecDeque<oneshot::Sender<()>>>,
+    last_served: Option<String>
+}
+
+impl Slots {
+    /// Next waiter in the lane after the last served one.
+    fn pop_next(&mut self) -> Option<oneshot::Sender<()>> {
+        let lane = match &self.last_served {
+            Some(last) => self.waiting.range::<String, _>((Bound::Excluded(last), Bound::Unbounded))
+                .next()
+                .or_else(|| self.waiting.iter().next())
+                .map(|(lane, _)| lane.clone()),
+            None => self.waiting.keys().next().cloned()
+        }?;
+        let queue = self.waiting.get_mut(&lane)?;
+        let waiter = queue.pop_front();
+        if queue.is_empty() {
+            self.waiting.remove(&lane);
+        }
+        self.last_served = Some(lane);
+        waiter
+    }
+}
+
+/// A slot of the concurrency cap, handed to the next waiter when dropped.
+#[derive(Debug)]
+pub struct Permit<'a> {
+    scheduler: &'a Scheduler
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.scheduler.release();
+    }
+}
+
+/// Gives the slot back if the waiting request is cancelled just after the slot was handed over.
+struct Waiting<'a> {
+    scheduler: &'a Scheduler,
+    rx: Option<oneshot::Receiver<()>>
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        if let Some(mut rx) = self.rx.take() {
+            rx.close();
+            if rx.try_recv().is_ok() {
+                self.scheduler.release();
+            }
+        }
+    }
+}
+
+impl Scheduler {
+    pub fn new(rate: f64, burst: f64, concurrency: usize) -> Scheduler {
+        Self {
+            rate,
+            burst,
+            concurrency,
+            bucket: Mutex::new(Bucket { tokens: burst, updated: Instant::now() }),
+            slots: Mutex::new(Slots::default())
+        }
+    }
+
+    /// Wait for a slot and a token, in the lane set by [`in_lane`].
+    pub async fn acquire(&self) -> Permit<'_> {
+        self.acquire_in(current_lane()).await
+    }
+
+    pub async fn acquire_in(&self, lane: String) -> Permit<'_> {
+        let waiting = {
+            let mut slots = self.slots.lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            if slots.in_flight < self.concurrency && slots.waiting.is_empty() {
+                slots.in_flight += 1;
+                None
+            } else {
+                let (tx, rx) = oneshot::channel();
+                slots.waiting.entry(lane).or_default().push_back(tx);
+                Some(rx)
+            }
+        };
+        if let Some(rx) = waiting {
+            let mut waiting = Waiting { scheduler: self, rx: Some(rx) };
+            if let Some(rx) = waiting.rx.as_mut() {
+                // senders live as long as the scheduler, so the slot is always handed over at last.
+                let _ = rx.await;
+            }
+            waiting.rx = None;
+        }
+        let permit = Permit { scheduler: self };
+        tokio::time::sleep(self.reserve_token()).await;
+        permit
+    }
+
+    /// Take a token, and return how long to wait for it.
+    fn reserve_token(&self) -> Duration {
+        let mut bucket = self.bucket.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let now = Instant::now();
+        let refilled = now.duration_since(bucket.updated).as_secs_f64() * self.rate;
+        bucket.tokens = (bucket.tokens + refilled).min(self.burst);
+        bucket.updated = now;
+        bucket.tokens -= 1.0;
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / self.rate)
+        }
+    }
+
+    fn release(&self) {
+        let mut slots = self.slots.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        // waiters that gave up have dropped their receiver, skip them.
+        while let Some(waiter) = slots.pop_next() {
+            if waiter.send(()).is_ok() {
+                return
+            }
+        }
+        slots.in_flight -= 1;
+    }
+}
+
+#[cfg(test)]
+mod scheduler_test {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use crate::entry::scheduler::Scheduler;
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limit_test() {
+        let scheduler = Scheduler::new(10.0, 2.0, 8);
+        let start = tokio::time::Instant::now();
+        for _ in 0..6 {
+            drop(scheduler.acquire_in(String::new()).await);
+        }
+        // 2 tokens at once, then 4 more at 10 per second.
+        assert_eq!(start.elapsed(), Duration::from_millis(400));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fair_order_test() {
+        let scheduler: &'static Scheduler = Box::leak(Box::new(Scheduler::new(1000.0, 1000.0, 1)));
+        let served = Arc::new(Mutex::new(Vec::new()));
+        let first = scheduler.acquire_in(String::from("hololive")).await;
+
+        let mut tasks = Vec::new();
+        for lane in ["hololive", "hololive", "hololive", "nijisanji"] {
+            let served = Arc::clone(&served);
+            tasks.push(tokio::spawn(async move {
+                let _permit = scheduler.acquire_in(lane.to_string()).await;
+                served.lock().unwrap().push(lane);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }));
+            tokio::task::yield_now().await;
+        }
+        drop(first);
+        for task in tasks {
+            task.await.expect("");
+        }
+        assert_eq!(*served.lock().unwrap(), vec!["hololive", "nijisanji", "hololive", "hololive"]);
+    }
+}
@@ -0,0 +1,61 @@
+use tracing::{info, warn};
+use crate::entry::api::YoutubeApiError;
+use crate::ids::StringId;
+use crate::models::Channel;
+
+/// A channel skipped in the run, with the error that caused it.
+#[derive(Debug, Clone)]
+pub struct ChannelFailure {
+    channel: StringId<Channel>,
+    error: YoutubeApiError
+}
+
+/// Result of a request run.
+///
+/// Failing channels are only skipped, so the run succeeds with the remaining channels
+/// and the failures are collected here.
+#[derive(Debug, Clone, Default)]
+pub struct RunSummary {
+    channels: usize,
+    failures: Vec<ChannelFailure>,
+    detail_failures: Vec<YoutubeApiError>
+}
+
+impl RunSummary {
+    pub fn requested(&mut self, channels: usize) {
+        self.channels += channels;
+    }
+
+    pub fn fail(&mut self, channel: StringId<Channel>, error: YoutubeApiError) {
+        warn!(channel = %channel, "skipped: {}", error);
+        self.failures.push(ChannelFailure { channel, error });
+    }
+
+    /// `videos` batch, which is not bound to a channel.
+    pub fn fail_details(&mut self, error: YoutubeApiError) {
+        warn!("skipped detail batch: {}", error);
+        self.detail_failures.push(error);
+    }
+
+    pub fn merge(&mut self, other: RunSummary) {
+        self.channels += other.channels;
+        self.failures.extend(other.failures);
+        self.detail_failures.extend(other.detail_failures);
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.failures.is_empty() && self.detail_failures.is_empty()
+    }
+
+    pub fn report(&self) {
+        if self.is_clean() {
+            info!("all {} channels succeeded.", self.channels);
+            return
+        }
+        warn!(failed_channels = self.failures.len(), failed_details = self.detail_failures.len(),
+            "{}/{} channels failed, {} detail batches failed.", self.failures.len(), self.channels, self.detail_failures.len());
+        for failure in self.failures.iter() {
+            warn!(channel = %failure.channel, " - {}: {}", failure.channel, failure.error);
+        }
+    }
+}
@@ -7,6 +7,7 @@ use chrono::Local;
 use once_cell::sync::OnceCell;
 use tonic::transport::Channel as GrpcChannel;
 
+use crate::entry::lifecycle::{LiveRecord, ScheduleChange};
 use crate::entry::request::{ChannelInfo, VideoInfo};
 use crate::entry::transport::salmon::{Affiliation, Liver, Channel, Live};
 use crate::entry::transport::salmon::salmon_api_client::SalmonApiClient;
@@ -80,11 +81,37 @@ impl From<crate::entry::request::VideoInfo> for Live {
             updated_at: Some(::prost_types::Timestamp::from(SystemTime::now())),
             will_start_at: base.as_ref_live_streaming_details().as_ref_scheduled_start_time_optional().map(|b| ::prost_types::Timestamp::from(SystemTime::from(b))),
             started_at: base.as_ref_live_streaming_details().as_ref_actual_start_time_optional().map(|b| ::prost_types::Timestamp::from(SystemTime::from(b))),
-            override_at: UpdateSignature::default().as_i64()
+            override_at: UpdateSignature::default().as_i64(),
+            view_count: *base.as_ref_statistics().as_ref_view_count(),
+            like_count: *base.as_ref_statistics().as_ref_like_count(),
+            favorite_count: *base.as_ref_statistics().as_ref_favorite_count(),
+            comment_count: *base.as_ref_statistics().as_ref_comment_count(),
+            concurrent_viewers: *base.as_ref_live_streaming_details().as_ref_concurrent_viewers_optional(),
+            reschedules: Vec::new()
+        }
+    }
+}
+
+impl From<&ScheduleChange> for salmon::ScheduleChange {
+    fn from(base: &ScheduleChange) -> Self {
+        Self {
+            from: Some(::prost_types::Timestamp::from(SystemTime::from(*base.as_ref_from()))),
+            to: Some(::prost_types::Timestamp::from(SystemTime::from(*base.as_ref_to()))),
+            delay_seconds: base.delay().num_seconds(),
+            changed_at: Some(::prost_types::Timestamp::from(SystemTime::from(*base.as_ref_changed_at())))
         }
     }
 }
 
+impl Applier<LiveRecord> for Live {
+    fn apply(mut self, apply: &LiveRecord) -> Self {
+        self.reschedules = apply.as_ref_history().iter()
+            .map(salmon::ScheduleChange::from)
+            .collect();
+        self
+    }
+}
+
 impl Applier<AffiliationEntry> for Liver {
     fn apply(mut self, apply: &AffiliationEntry) -> Self {
         self.affiliation_id = Some(apply.breach_extraction_id().breach_extract());
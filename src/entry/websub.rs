@@ -0,0 +1,351 @@
+use std::collections::{HashMap, HashSet};
+use std::collections::vec_deque::VecDeque;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+use anyhow::Context;
+use chrono::{DateTime, Local};
+use hmac::{Hmac, Mac};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::service::{make_service_fn, service_fn};
+use once_cell::sync::OnceCell;
+use reqwest::Url;
+use serde::Deserialize;
+use sha1::Sha1;
+use tracing::{debug, error, info, info_span, Instrument, warn};
+use crate::entry::feed::parse_feed;
+use crate::entry::{get_or_init_config, push_notified_lives};
+use crate::entry::request::{get_http_client, request_video_details, VideoInfo};
+use crate::entry::summary::RunSummary;
+use crate::ids::StringId;
+use crate::models::{AffiliationEntry, Channel};
+
+const DEFAULT_HUB: &str = "https://pubsubhubbub.appspot.com/subscribe";
+const TOPIC_BASE: &str = "https://www.youtube.com/xml/feeds/videos.xml";
+const DEFAULT_LEASE_SECONDS: i64 = 5 * 24 * 60 * 60;
+const RENEW_CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Public url of this receiver, the receiver is enabled only when this is set.
+pub fn get_callback_url() -> Option<&'static str> {
+    static CALLBACK: OnceCell<Option<String>> = OnceCell::new();
+    CALLBACK.get_or_init(|| {
+        dotenv::var("WEBSUB_CALLBACK_URL").ok()
+    }).as_deref()
+}
+
+fn get_bind_address() -> &'static SocketAddr {
+    static ADDRESS: OnceCell<SocketAddr> = OnceCell::new();
+    ADDRESS.get_or_init(|| {
+        dotenv::var("WEBSUB_BIND")
+            .ok()
+            .and_then(|f| f.parse().ok())
+            .unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 8080)))
+    })
+}
+
+fn get_hub_url() -> &'static str {
+    static HUB: OnceCell<String> = OnceCell::new();
+    HUB.get_or_init(|| {
+        dotenv::var("WEBSUB_HUB")
+            .unwrap_or_else(|_| DEFAULT_HUB.to_string())
+    })
+}
+
+fn get_secret() -> Option<&'static str> {
+    static SECRET: OnceCell<Option<String>> = OnceCell::new();
+    SECRET.get_or_init(|| {
+        dotenv::var("WEBSUB_SECRET").ok()
+    }).as_deref()
+}
+
+fn get_lease_seconds() -> &'static i64 {
+    static LEASE: OnceCell<i64> = OnceCell::new();
+    LEASE.get_or_init(|| {
+        dotenv::var("WEBSUB_LEASE_SECONDS")
+            .ok()
+            .and_then(|f| f.parse().ok())
+            .unwrap_or(DEFAULT_LEASE_SECONDS)
+    })
+}
+
+/// Youtube channel in roster -> affiliation.
+fn get_roster() -> &'static HashMap<StringId<Channel>, &'static AffiliationEntry> {
+    static ROSTER: OnceCell<HashMap<StringId<Channel>, &'static AffiliationEntry>> = OnceCell::new();
+    ROSTER.get_or_init(|| {
+        get_or_init_config().iter()
+            .flat_map(|(aff, livers)| livers.iter()
+                .flat_map(|liver| liver.as_ref_site().iter()
+                    .flat_map(Channel::as_youtube_id))
+                .map(move |id| (id, aff)))
+            .collect()
+    })
+}
+
+/// Lease expiration per channel, updated on subscription verification.
+fn get_leases() -> &'static Mutex<HashMap<StringId<Channel>, DateTime<Local>>> {
+    static LEASES: OnceCell<Mutex<HashMap<StringId<Channel>, DateTime<Local>>>> = OnceCell::new();
+    LEASES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+pub(super) fn topic_url(channel: &StringId<Channel>) -> String {
+    format!("{}?channel_id={}", TOPIC_BASE, channel.as_ref())
+}
+
+fn channel_of_topic(topic: &str) -> Option<StringId<Channel>> {
+    Url::parse(topic).ok()?
+        .query_pairs()
+        .find(|(key, _)| key == "channel_id")
+        .map(|(_, value)| StringId::new(value.into_owned()))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebSubError {
+    #[error("cannot bind receiver to {}.", .0)]
+    Bind(SocketAddr),
+    #[error("failed subscription request.")]
+    Subscribe,
+    #[error("hub refused subscription: {}", .0)]
+    Refused(reqwest::StatusCode),
+}
+
+/// Serve WebSub (PubSubHubbub) receiver, and keep subscriptions of every youtube channel in roster.
+///
+/// Each notification triggers `videos` details lookup of the notified videos,
+/// and only the notified ones are cached and pushed, the rest is left to the periodic request.
+pub(super) async fn serve() -> anyhow::Result<()> {
+    let address = *get_bind_address();
+    let service = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(route))
+    });
+    let server = Server::try_bind(&address)
+        .context(WebSubError::Bind(address))?
+        .serve(service);
+    info!("WebSub receiver listening on {}", address);
+
+    tokio::spawn(renew_subscriptions().instrument(info_span!("websub")));
+
+    server.await?;
+    Ok(())
+}
+
+async fn route(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let span = info_span!("websub");
+    let res = match *req.method() {
+        Method::GET => span.in_scope(|| verify(&req)),
+        Method::POST => notify(req).instrument(span).await,
+        _ => status(StatusCode::METHOD_NOT_ALLOWED)
+    };
+    Ok(res)
+}
+
+fn status(code: StatusCode) -> Response<Body> {
+    let mut res = Response::new(Body::empty());
+    *res.status_mut() = code;
+    res
+}
+
+#[derive(Debug, Deserialize)]
+struct HubVerification {
+    #[serde(rename = "hub.mode")]
+    mode: String,
+    #[serde(rename = "hub.topic")]
+    topic: String,
+    #[serde(rename = "hub.challenge")]
+    challenge: String,
+    #[serde(rename = "hub.lease_seconds")]
+    lease_seconds: Option<i64>
+}
+
+/// Intent verification of subscribe / unsubscribe, answered by echoing `hub.challenge`.
+fn verify(req: &Request<Body>) -> Response<Body> {
+    let query = match serde_urlencoded::from_str::<HubVerification>(req.uri().query().unwrap_or_default()) {
+        Ok(query) => query,
+        Err(reason) => {
+            debug!("invalid verification request: {}", reason);
+            return status(StatusCode::BAD_REQUEST)
+        }
+    };
+    let channel = match channel_of_topic(&query.topic).filter(|channel| get_roster().contains_key(channel)) {
+        Some(channel) => channel,
+        None => {
+            warn!("refused verification of unknown topic: {}", query.topic);
+            return status(StatusCode::NOT_FOUND)
+        }
+    };
+    let mut leases = get_leases().lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    match query.mode.as_str() {
+        "subscribe" => {
+            let lease = query.lease_seconds.unwrap_or(*get_lease_seconds());
+            leases.insert(channel.to_owned(), Local::now() + chrono::Duration::seconds(lease));
+            info!(channel = %channel, "subscription verified, lease {}sec", lease);
+        },
+        "unsubscribe" => {
+            leases.remove(&channel);
+            info!(channel = %channel, "unsubscription verified");
+        },
+        other => {
+            warn!(channel = %channel, "unknown hub.mode: {}", other);
+            return status(StatusCode::BAD_REQUEST)
+        }
+    }
+    Response::new(Body::from(query.challenge))
+}
+
+/// Content distribution. Always answered with 2xx, since hub retries on failure.
+async fn notify(req: Request<Body>) -> Response<Body> {
+    let signature = req.headers().get("x-hub-signature")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(reason) => {
+            warn!("cannot read notification: {}", reason);
+            return status(StatusCode::BAD_REQUEST)
+        }
+    };
+    if let Some(secret) = get_secret() {
+        if !is_valid_signature(secret, signature.as_deref(), &body) {
+            warn!("ignored notification with invalid signature.");
+            return status(StatusCode::NO_CONTENT)
+        }
+    }
+    let entries = match parse_feed(&String::from_utf8_lossy(&body)) {
+        Ok(entries) => entries,
+        Err(reason) => {
+            warn!("cannot parse notification: {:?}", reason);
+            return status(StatusCode::NO_CONTENT)
+        }
+    };
+    let ids = entries.into_iter()
+        .filter(|entry| get_roster().contains_key(entry.as_ref_channel_id()))
+        .inspect(|entry| info!(channel = %entry.as_ref_channel_id(), video = %entry.as_ref_video_id(), "notified {}", entry.as_ref_title()))
+        .map(|entry| entry.as_ref_video_id().to_owned())
+        .collect::<HashSet<StringId<VideoInfo>>>();
+    if ids.is_empty() {
+        return status(StatusCode::NO_CONTENT)
+    }
+
+    tokio::spawn(async move {
+        // a notification means the videos have changed, so they are downloaded without etags.
+        let videos = match request_video_details(ids.into_iter().collect(), None, &mut RunSummary::default()).await {
+            Ok(videos) => videos,
+            Err(reason) => {
+                error!("failed detail search of notified videos: {:?}", reason);
+                return
+            }
+        };
+        let mut grouped: HashMap<&'static str, (&'static AffiliationEntry, VecDeque<VideoInfo>)> = HashMap::new();
+        // finished or filtered videos are also handed over, to update their states.
+        for video in videos {
+            // the notified channel is checked again by the actual owner of the video.
+            if let Some(aff) = get_roster().get(video.as_ref_snippet().as_ref_dependency_channel_id()) {
+                grouped.entry(aff.as_ref_name()).or_insert_with(|| (aff, VecDeque::new())).1.push_back(video);
+            }
+        }
+        for (_, (aff, videos)) in grouped {
+            push_notified_lives(aff, videos).await;
+        }
+    }.in_current_span());
+
+    status(StatusCode::NO_CONTENT)
+}
+
+/// `X-Hub-Signature: sha1=<hex of HMAC-SHA1(secret, body)>`
+fn is_valid_signature(secret: &str, signature: Option<&str>, body: &[u8]) -> bool {
+    let expected = match signature.and_then(|signature| signature.strip_prefix("sha1=")).and_then(|hex| hex::decode(hex).ok()) {
+        Some(expected) => expected,
+        None => return false
+    };
+    let mut mac = match Hmac::<Sha1>::new_from_slice(secret.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return false
+    };
+    mac.update(body);
+    mac.verify_slice(&expected).is_ok()
+}
+
+async fn subscribe(channel: &StringId<Channel>, callback: &str) -> anyhow::Result<()> {
+    let lease = get_lease_seconds().to_string();
+    let topic = topic_url(channel);
+    let mut form = vec![
+        ("hub.callback", callback),
+        ("hub.topic", topic.as_str()),
+        ("hub.mode", "subscribe"),
+        ("hub.verify", "async"),
+        ("hub.lease_seconds", lease.as_str())
+    ];
+    if let Some(secret) = get_secret() {
+        form.push(("hub.secret", secret));
+    }
+    let res = get_http_client().post(get_hub_url())
+        .form(&form)
+        .send().await
+        .context(WebSubError::Subscribe)?;
+    if !res.status().is_success() {
+        return Err(WebSubError::Refused(res.status()).into())
+    }
+    Ok(())
+}
+
+/// Subscribe every channel at first, then renew the ones whose lease expires
+/// within 10% of the lease (or were never verified) on every check.
+async fn renew_subscriptions() {
+    let callback = match get_callback_url() {
+        Some(callback) => callback,
+        None => return
+    };
+    let margin = chrono::Duration::seconds(*get_lease_seconds() / 10);
+    let mut requested: HashMap<StringId<Channel>, DateTime<Local>> = HashMap::new();
+    loop {
+        let now = Local::now();
+        let expiring = {
+            let leases = get_leases().lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            get_roster().keys()
+                .filter(|channel| leases.get(channel).map(|expiration| *expiration - margin <= now).unwrap_or(true))
+                // requested already, request again only if hub did not verify within the margin.
+                .filter(|channel| requested.get(channel).map(|at| *at + margin <= now).unwrap_or(true))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        for channel in expiring {
+            match subscribe(&channel, callback).await {
+                Ok(_) => debug!(channel = %channel, "subscription requested"),
+                Err(reason) => error!(channel = %channel, "cannot subscribe: {:?}", reason)
+            }
+            requested.insert(channel, now);
+        }
+        tokio::time::sleep(RENEW_CHECK_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod websub_test {
+    use hmac::{Hmac, Mac};
+    use sha1::Sha1;
+    use crate::entry::websub::{channel_of_topic, is_valid_signature, topic_url};
+    use crate::ids::StringId;
+    use crate::models::Channel;
+
+    #[test]
+    fn topic_test() {
+        let channel = StringId::<Channel>::new("UCvaTdHTWBGv3MKj3KVqJVCw");
+        assert_eq!(channel_of_topic(&topic_url(&channel)), Some(channel));
+        assert_eq!(channel_of_topic("https://www.youtube.com/xml/feeds/videos.xml"), None);
+    }
+
+    #[test]
+    fn signature_test() {
+        let body = b"<feed></feed>";
+        let mut mac = Hmac::<Sha1>::new_from_slice(b"secret").expect("");
+        mac.update(body);
+        let signature = format!("sha1={}", hex::encode(mac.finalize().into_bytes()));
+
+        assert!(is_valid_signature("secret", Some(&signature), body));
+        assert!(!is_valid_signature("other", Some(&signature), body));
+        assert!(!is_valid_signature("secret", None, body));
+        assert!(!is_valid_signature("secret", Some("sha1=zz"), body));
+    }
+}
@@ -2,9 +2,27 @@
 
 use std::fmt::{Display, Formatter};
 use std::marker::PhantomData;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde::de::{Error, Unexpected, Visitor};
 
-#[derive(Debug, Deserialize, Serialize, Clone, Copy, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, thiserror::Error)]
+pub enum IdFormatError {
+    #[error("`{}` is not a youtube channel id. (expected `UC` + 22 characters)", .0)]
+    Channel(String),
+    #[error("`{}` is not a youtube video id. (expected 11 characters)", .0)]
+    Video(String),
+    #[error("`{}` is not a youtube playlist id.", .0)]
+    Playlist(String),
+    #[error("`{}` is not a youtube handle. (expected `@` + 3-30 characters)", .0)]
+    Handle(String),
+}
+
+/// Format rule of the id held by `StringId<Self>`.
+pub trait IdFormat {
+    fn validate(id: &str) -> Result<(), IdFormatError>;
+}
+
+#[derive(Debug, Serialize, Clone, Copy, Eq, PartialEq, Hash)]
 #[serde(transparent)]
 pub struct NumId<T> {
     value: i64,
@@ -32,6 +50,72 @@ impl<T> Display for NumId<T> {
     }
 }
 
+/// Accepts both `8070714237840614` and `"8070714237840614"`.
+impl<'de, T> Deserialize<'de> for NumId<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        struct NumIdVisitor<T>(PhantomData<fn() -> T>);
+        impl<'de, T> Visitor<'de> for NumIdVisitor<T> {
+            type Value = NumId<T>;
+            fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+                formatter.write_str("integer or numeric string id")
+            }
+
+            fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
+                where E: Error
+            {
+                Ok(NumId::new(v))
+            }
+
+            fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+                where E: Error
+            {
+                i64::try_from(v)
+                    .map(NumId::new)
+                    .map_err(|_| E::invalid_value(Unexpected::Unsigned(v), &self))
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+                where E: Error
+            {
+                v.trim().parse::<i64>()
+                    .map(NumId::new)
+                    .map_err(|_| E::invalid_value(Unexpected::Str(v), &self))
+            }
+        }
+        deserializer.deserialize_any(NumIdVisitor(PhantomData))
+    }
+}
+
+/// Opt-in string representation for [`NumId`].
+///
+/// Our ids exceed `2^53`, which JavaScript cannot represent as a number.
+///
+/// ```rust
+/// #[derive(Serialize, Deserialize)]
+/// struct Entry {
+///     #[serde(with = "crate::ids::stringify")]
+///     id: NumId<Entry>
+/// }
+/// ```
+pub mod stringify {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use crate::ids::NumId;
+
+    pub fn serialize<T, S>(id: &NumId<T>, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.collect_str(id)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<NumId<T>, D::Error>
+        where D: Deserializer<'de>
+    {
+        NumId::deserialize(deserializer)
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, Eq, PartialEq, Hash)]
 #[serde(transparent)]
 pub struct StringId<T> {
@@ -54,6 +138,14 @@ impl<T> StringId<T> {
     }
 }
 
+impl<T: IdFormat> StringId<T> {
+    pub fn try_new(id: impl Into<String>) -> Result<StringId<T>, IdFormatError> {
+        let id = id.into();
+        T::validate(&id)?;
+        Ok(Self::new(id))
+    }
+}
+
 impl<T> Display for StringId<T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         Display::fmt(&self.value, f)
@@ -72,16 +164,76 @@ impl<T> From<StringId<T>> for String {
     }
 }
 
+/// Use as `#[serde(deserialize_with = "crate::ids::validated")]`
+/// to reject malformed ids while loading config.
+pub fn validated<'de, T, D>(deserializer: D) -> Result<StringId<T>, D::Error>
+    where T: IdFormat,
+          D: Deserializer<'de>
+{
+    StringId::try_new(String::deserialize(deserializer)?)
+        .map_err(D::Error::custom)
+}
+
+/// Optional version of [`validated`], use with `#[serde(default)]`.
+pub fn validated_optional<'de, T, D>(deserializer: D) -> Result<Option<StringId<T>>, D::Error>
+    where T: IdFormat,
+          D: Deserializer<'de>
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(StringId::try_new)
+        .transpose()
+        .map_err(D::Error::custom)
+}
+
+pub mod youtube {
+    use once_cell::sync::OnceCell;
+    use regex::Regex;
+
+    fn matches(cell: &'static OnceCell<Regex>, pattern: &str, id: &str) -> bool {
+        cell.get_or_init(|| Regex::new(pattern).unwrap()).is_match(id)
+    }
+
+    /// `UC` + 22 characters.
+    pub fn is_channel_id(id: &str) -> bool {
+        static REGEX: OnceCell<Regex> = OnceCell::new();
+        matches(&REGEX, "^UC[0-9A-Za-z_-]{22}$", id)
+    }
+
+    /// 11 characters.
+    pub fn is_video_id(id: &str) -> bool {
+        static REGEX: OnceCell<Regex> = OnceCell::new();
+        matches(&REGEX, "^[0-9A-Za-z_-]{11}$", id)
+    }
+
+    /// `PL`, `UU`(uploads), `LL`, `FL`, `OL`, `RD` and `UL` prefixed ids.
+    pub fn is_playlist_id(id: &str) -> bool {
+        static REGEX: OnceCell<Regex> = OnceCell::new();
+        matches(&REGEX, "^(PL|UU|LL|FL|OL|RD|UL)[0-9A-Za-z_-]{10,}$", id)
+    }
+
+    /// `@` + 3-30 letters, digits, `_`, `-` or `.`.
+    pub fn is_handle(id: &str) -> bool {
+        static REGEX: OnceCell<Regex> = OnceCell::new();
+        matches(&REGEX, r"^@[\p{L}\p{N}_.-]{3,30}$", id)
+    }
+}
+
 #[cfg(test)]
 mod id_test {
     use serde::{Deserialize, Serialize};
-    use crate::ids::NumId;
+    use crate::ids::{IdFormat, IdFormatError, NumId, StringId, youtube};
 
     #[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
     struct Entry {
         id: NumId<Entry>
     }
 
+    #[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+    struct StringifiedEntry {
+        #[serde(with = "crate::ids::stringify")]
+        id: NumId<StringifiedEntry>
+    }
+
     #[test]
     fn parse_test() {
         let e = Entry {
@@ -93,4 +245,62 @@ mod id_test {
         let d: Entry = serde_json::from_str(&s).expect("");
         assert_eq!(e, d);
     }
+    #[test]
+    fn accept_both_form_test() {
+        let num: Entry = serde_json::from_str(r#"{ "id": 8070714237840614 }"#).expect("");
+        let str: Entry = serde_json::from_str(r#"{ "id": "8070714237840614" }"#).expect("");
+        assert_eq!(num, str);
+        assert_eq!(num.id.breach_extract(), 8070714237840614);
+
+        assert!(serde_json::from_str::<Entry>(r#"{ "id": "nijisanji" }"#).is_err());
+        assert!(serde_json::from_str::<Entry>(r#"{ "id": 18446744073709551615 }"#).is_err());
+    }
+
+    #[test]
+    fn stringify_test() {
+        let e = StringifiedEntry {
+            id: NumId::new(8070714237840614)
+        };
+
+        let s = serde_json::to_string(&e).expect("");
+        assert_eq!(s, r#"{"id":"8070714237840614"}"#);
+        let d: StringifiedEntry = serde_json::from_str(&s).expect("");
+        assert_eq!(e, d);
+    }
+    #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize)]
+    struct YoutubeEntry {
+        #[serde(deserialize_with = "crate::ids::validated")]
+        id: StringId<YoutubeEntry>
+    }
+
+    impl IdFormat for YoutubeEntry {
+        fn validate(id: &str) -> Result<(), IdFormatError> {
+            if !youtube::is_channel_id(id) {
+                return Err(IdFormatError::Channel(id.to_string()))
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn youtube_format_test() {
+        assert!(youtube::is_channel_id("UCvaTdHTWBGv3MKj3KVqJVCw"));
+        assert!(!youtube::is_channel_id("UCvaTdHTWBGv3MKj3KVqJVC"));
+        assert!(!youtube::is_channel_id("@nekomataokayu"));
+        assert!(youtube::is_video_id("dQw4w9WgXcQ"));
+        assert!(!youtube::is_video_id("dQw4w9WgXc"));
+        assert!(youtube::is_playlist_id("UUvaTdHTWBGv3MKj3KVqJVCw"));
+        assert!(!youtube::is_playlist_id("UCvaTdHTWBGv3MKj3KVqJVCw"));
+        assert!(youtube::is_handle("@nekomataokayu"));
+        assert!(youtube::is_handle("@猫又おかゆ"));
+        assert!(!youtube::is_handle("nekomataokayu"));
+        assert!(!youtube::is_handle("@ab"));
+
+        assert!(StringId::<YoutubeEntry>::try_new("UCvaTdHTWBGv3MKj3KVqJVCw").is_ok());
+        assert_eq!(StringId::<YoutubeEntry>::try_new("UCvaTdHTWBGv3MKj3KVqJVC"),
+                   Err(IdFormatError::Channel("UCvaTdHTWBGv3MKj3KVqJVC".to_string())));
+
+        assert!(serde_json::from_str::<YoutubeEntry>(r#"{ "id": "UCvaTdHTWBGv3MKj3KVqJVCw" }"#).is_ok());
+        assert!(serde_json::from_str::<YoutubeEntry>(r#"{ "id": "UCvaTdHTWBGv3MKj3KVqJVC" }"#).is_err());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,165 @@
+use std::fmt::Write as _;
+use chrono::Local;
+use serde_json::{Map, Value};
+use tracing::{Event, Level, Subscriber};
+use tracing::field::{Field, Visit};
+use tracing::span::Record;
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::registry::LookupSpan;
+use yansi::{Paint, Color};
+
+/// Colored human readable format.
+///
+/// The innermost span name is rendered as the component column,
+/// and fields of every entered span are appended after the message.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HumanFormat;
+
+impl HumanFormat {
+    fn level(level: &Level) -> (&'static str, Color) {
+        match *level {
+            Level::ERROR => ("Error", Color::Red),
+            Level::WARN => ("Warn", Color::Magenta),
+            Level::INFO => ("Info", Color::Cyan),
+            Level::DEBUG => ("Debug", Color::Magenta),
+            Level::TRACE => ("Trace", Color::White),
+        }
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for HumanFormat
+    where S: Subscriber + for<'a> LookupSpan<'a>,
+          N: for<'a> FormatFields<'a> + 'static
+{
+    fn format_event(&self, ctx: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> std::fmt::Result {
+        let ansi = writer.has_ansi_escapes();
+        let (level, level_color) = Self::level(event.metadata().level());
+        let level = format!("{:<5}", level);
+        write!(writer, "[{}] [ {:^4} ] ",
+               Local::now().format("%H:%M:%S - %m/%d"),
+               if ansi { Paint::new(level).fg(level_color) } else { Paint::new(level) })?;
+
+        if let Some(span) = ctx.lookup_current() {
+            let name = format!("{:<12}", span.name());
+            write!(writer, "[ {} ] ", if ansi { Paint::green(name) } else { Paint::new(name) })?;
+        }
+
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+
+        let mut context = String::new();
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                if let Some(fields) = extensions.get::<FormattedFields<N>>() {
+                    if !fields.is_empty() {
+                        write!(context, " {}", fields)?;
+                    }
+                }
+            }
+        }
+        if !context.is_empty() {
+            write!(writer, " {}", if ansi { Paint::new(context).dimmed() } else { Paint::new(context) })?;
+        }
+
+        writeln!(writer)
+    }
+}
+
+/// One JSON object per line.
+///
+/// ```json
+/// {"timestamp":"2022-04-01T12:00:00.000+09:00","level":"INFO","component":"Request","target":"salmon::entry","message":"Request << hololive","fields":{"affiliation":"hololive"}}
+/// ```
+///
+/// `component` is the innermost span name, and `fields` contains the fields of
+/// every entered span and of the event itself. Requires [`JsonFields`] as field formatter.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonFormat;
+
+impl<S> FormatEvent<S, JsonFields> for JsonFormat
+    where S: Subscriber + for<'a> LookupSpan<'a>
+{
+    fn format_event(&self, ctx: &FmtContext<'_, S, JsonFields>, mut writer: Writer<'_>, event: &Event<'_>) -> std::fmt::Result {
+        let mut fields = Map::new();
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                if let Some(recorded) = extensions.get::<FormattedFields<JsonFields>>() {
+                    if let Ok(Value::Object(recorded)) = serde_json::from_str::<Value>(recorded) {
+                        fields.extend(recorded);
+                    }
+                }
+            }
+        }
+
+        let mut visitor = JsonVisitor::default();
+        event.record(&mut visitor);
+        let message = visitor.0.remove("message").unwrap_or(Value::Null);
+        fields.extend(visitor.0);
+
+        let mut line = Map::new();
+        line.insert("timestamp".to_string(), Value::from(Local::now().to_rfc3339()));
+        line.insert("level".to_string(), Value::from(event.metadata().level().to_string()));
+        line.insert("component".to_string(), ctx.lookup_current()
+            .map(|span| Value::from(span.name()))
+            .unwrap_or(Value::Null));
+        line.insert("target".to_string(), Value::from(event.metadata().target()));
+        line.insert("message".to_string(), message);
+        line.insert("fields".to_string(), Value::Object(fields));
+
+        writeln!(writer, "{}", Value::Object(line))
+    }
+}
+
+/// Records span fields as a JSON object, so that [`JsonFormat`] can merge them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonFields;
+
+impl<'writer> FormatFields<'writer> for JsonFields {
+    fn format_fields<R: RecordFields>(&self, mut writer: Writer<'writer>, fields: R) -> std::fmt::Result {
+        let mut visitor = JsonVisitor::default();
+        fields.record(&mut visitor);
+        write!(writer, "{}", Value::Object(visitor.0))
+    }
+
+    fn add_fields(&self, current: &'writer mut FormattedFields<Self>, fields: &Record<'_>) -> std::fmt::Result {
+        let mut visitor = match serde_json::from_str::<Value>(current) {
+            Ok(Value::Object(recorded)) => JsonVisitor(recorded),
+            _ => JsonVisitor::default()
+        };
+        fields.record(&mut visitor);
+        current.fields = Value::Object(visitor.0).to_string();
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct JsonVisitor(Map<String, Value>);
+
+impl Visit for JsonVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0.insert(field.name().to_string(), Value::from(format!("{:?}", value)));
+    }
+}
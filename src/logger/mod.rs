@@ -0,0 +1,115 @@
+mod format;
+mod rotation;
+
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+pub use self::format::{HumanFormat, JsonFields, JsonFormat};
+pub use self::rotation::{Rotation, RotatingFile};
+
+const DEFAULT_FILTER: &str = "info";
+
+/// Install the global `tracing` subscriber.
+///
+/// Filter directives are read from `SALMON_LOG` (same syntax as `RUST_LOG`),
+/// and fall back to `info` when not set.
+///
+/// ## Usage
+///
+/// ```rust
+/// logger::init();
+///
+/// let _span = tracing::info_span!("logger", affiliation = "hololive").entered();
+///
+/// // [%H:%M:%S - %m/%d] [ Info  ] [ logger       ] some information log  affiliation=hololive
+/// tracing::info!("some information log");
+/// ```
+///
+/// ## Environment
+///
+/// | key             | default | description                                   |
+/// |-----------------|---------|-----------------------------------------------|
+/// | `SALMON_LOG`    | `info`  | e.g. `warn,salmon::entry::request=debug`      |
+/// | `LOG_FORMAT`    | `human` | `human` or `json` (one object per line)       |
+/// | `LOG_FILE`      | -       | also write logs to this file                  |
+/// | `LOG_ROTATION`  | `daily` | `daily`, `size` or `never`                    |
+/// | `LOG_MAX_BYTES` | 10MiB   | threshold of `size` rotation                  |
+/// | `LOG_RETENTION` | `7`     | number of rotated files to keep               |
+pub fn init() {
+    let format: LogFormat = dotenv::var("LOG_FORMAT")
+        .ok()
+        .and_then(|format| format.parse().ok())
+        .unwrap_or_default();
+
+    let mut layers = vec![format.layer(std::io::stdout, true)];
+
+    if let Ok(path) = dotenv::var("LOG_FILE") {
+        match RotatingFile::open(&path, Rotation::from_env(), get_log_retention()) {
+            Ok(file) => layers.push(format.layer(Arc::new(file), false)),
+            Err(reason) => eprintln!("cannot open log file {}: {}", path, reason)
+        }
+    }
+
+    tracing_subscriber::registry()
+        .with(layers)
+        .init();
+}
+
+fn get_env_filter() -> EnvFilter {
+    dotenv::var("SALMON_LOG")
+        .ok()
+        .and_then(|directive| EnvFilter::try_new(directive).ok())
+        .unwrap_or_else(|| EnvFilter::new(DEFAULT_FILTER))
+}
+
+fn get_log_retention() -> usize {
+    dotenv::var("LOG_RETENTION")
+        .ok()
+        .and_then(|f| f.parse().ok())
+        .unwrap_or(7)
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum LogFormat {
+    #[default]
+    Human,
+    Json
+}
+
+impl LogFormat {
+    fn layer<W>(self, writer: W, ansi: bool) -> Box<dyn Layer<Registry> + Send + Sync>
+        where W: for<'a> MakeWriter<'a> + Send + Sync + 'static
+    {
+        match self {
+            LogFormat::Human => tracing_subscriber::fmt::layer()
+                .with_ansi(ansi)
+                .event_format(HumanFormat)
+                .with_writer(writer)
+                .with_filter(get_env_filter())
+                .boxed(),
+            LogFormat::Json => tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .fmt_fields(JsonFields)
+                .event_format(JsonFormat)
+                .with_writer(writer)
+                .with_filter(get_env_filter())
+                .boxed()
+        }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "human" => Ok(LogFormat::Human),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("unknown log format: {}", other))
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use chrono::{DateTime, Local, NaiveDate};
+
+const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Rotation {
+    Never,
+    Daily,
+    Size(u64)
+}
+
+impl Rotation {
+    /// Read from `LOG_ROTATION` and `LOG_MAX_BYTES`.
+    pub fn from_env() -> Rotation {
+        match dotenv::var("LOG_ROTATION").unwrap_or_default().to_ascii_lowercase().as_str() {
+            "never" => Rotation::Never,
+            "size" => Rotation::Size(dotenv::var("LOG_MAX_BYTES")
+                .ok()
+                .and_then(|f| f.parse().ok())
+                .unwrap_or(DEFAULT_MAX_BYTES)),
+            _ => Rotation::Daily
+        }
+    }
+}
+
+/// Log file which is rotated by date or size.
+///
+/// The active file always has the configured name,
+/// rotated files get a suffix such as `salmon.log.2022-04-01` or `salmon.log.20220401-120000`.
+/// Only the newest `retention` rotated files are kept.
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    retention: usize,
+    state: Mutex<State>
+}
+
+#[derive(Debug)]
+struct State {
+    file: File,
+    opened: NaiveDate,
+    size: u64
+}
+
+impl RotatingFile {
+    pub fn open(path: impl AsRef<Path>, rotation: Rotation, retention: usize) -> std::io::Result<RotatingFile> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let metadata = file.metadata()?;
+        let opened = metadata.modified()
+            .map(|modified| DateTime::<Local>::from(modified).date_naive())
+            .unwrap_or_else(|_| Local::now().date_naive());
+        Ok(Self {
+            path,
+            rotation,
+            retention,
+            state: Mutex::new(State { file, opened, size: metadata.len() })
+        })
+    }
+
+    fn should_rotate(&self, state: &State, incoming: usize) -> bool {
+        match self.rotation {
+            Rotation::Never => false,
+            Rotation::Daily => state.opened != Local::now().date_naive(),
+            Rotation::Size(max) => state.size > 0 && state.size + incoming as u64 > max
+        }
+    }
+
+    fn rotate(&self, state: &mut State) -> std::io::Result<()> {
+        state.file.flush()?;
+        let suffix = match self.rotation {
+            Rotation::Daily => state.opened.format("%Y-%m-%d").to_string(),
+            _ => Local::now().format("%Y%m%d-%H%M%S%.9f").to_string()
+        };
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(format!(".{}", suffix));
+        let mut rotated = PathBuf::from(rotated);
+        while rotated.exists() {
+            let mut next = rotated.into_os_string();
+            next.push("_");
+            rotated = PathBuf::from(next);
+        }
+        std::fs::rename(&self.path, rotated)?;
+
+        state.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        state.opened = Local::now().date_naive();
+        state.size = 0;
+        self.remove_expired()
+    }
+
+    fn remove_expired(&self) -> std::io::Result<()> {
+        let name = match self.path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => format!("{}.", name),
+            None => return Ok(())
+        };
+        let dir = match self.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            Some(parent) => parent.to_path_buf(),
+            None => PathBuf::from(".")
+        };
+        let mut rotated = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_str()
+                .map(|file| file.starts_with(&name))
+                .unwrap_or(false))
+            .map(|entry| entry.path())
+            .collect::<Vec<_>>();
+        // suffixes are sortable timestamps.
+        rotated.sort();
+        let expired = rotated.len().saturating_sub(self.retention);
+        for path in rotated.into_iter().take(expired) {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for &RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut state = self.state.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if self.should_rotate(&state, buf.len()) {
+            self.rotate(&mut state)?;
+        }
+        let written = state.file.write(buf)?;
+        state.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.state.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .file.flush()
+    }
+}
+
+#[cfg(test)]
+mod rotation_test {
+    use std::io::Write;
+    use crate::logger::{Rotation, RotatingFile};
+
+    #[test]
+    fn size_rotation_test() {
+        let dir = std::env::temp_dir().join(format!("salmon_rotation_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let file = RotatingFile::open(dir.join("salmon.log"), Rotation::Size(16), 2).expect("");
+
+        for _ in 0..5 {
+            (&file).write_all(b"0123456789\n").expect("");
+        }
+
+        let mut names = std::fs::read_dir(&dir).expect("")
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().into_string().expect(""))
+            .collect::<Vec<_>>();
+        names.sort();
+
+        // active file + 2 retained.
+        assert_eq!(names.len(), 3);
+        assert_eq!(names[0], "salmon.log");
+        assert_eq!(std::fs::read_to_string(dir.join("salmon.log")).expect(""), "0123456789\n");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
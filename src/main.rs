@@ -1,15 +1,33 @@
 #![forbid(unsafe_code, unsafe_op_in_unsafe_fn)]
 extern crate serde;
 
+mod cli;
 mod logger;
 mod repository;
 mod ids;
 mod entry;
 mod models;
 
+use clap::Parser;
+use crate::cli::{Cli, Command, SamplesCommand};
+
 #[tokio::main]
 async fn main() {
-    repository::setup_config_repository();
-    entry::channel_info_request_handler().await.expect("");
-    entry::upcoming_live_request_handler().await.expect("");
-}
\ No newline at end of file
+    let cli = Cli::parse();
+    logger::init();
+    match cli.command {
+        Some(Command::Samples(SamplesCommand::Export { format, video, out })) => {
+            entry::export_samples(format, video, out).await.expect("cannot export samples");
+        },
+        Some(Command::Cache(command)) => {
+            entry::cache_command(command).await.expect("cannot run cache command");
+        },
+        None => {
+            repository::setup_config_repository();
+            tokio::spawn(entry::lifecycle_handler());
+            entry::channel_info_request_handler().await.expect("");
+            entry::upcoming_live_request_handler().await.expect("");
+            tokio::try_join!(entry::polling_handler(), entry::websub_handler(), entry::sampler_handler()).expect("");
+        }
+    }
+}
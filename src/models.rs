@@ -7,14 +7,16 @@ use std::path::Path;
 use anyhow::Context;
 use serde::{Deserialize};
 
-use crate::ids::{NumId, StringId};
+use crate::ids::{IdFormat, IdFormatError, NumId, StringId, youtube};
 
 #[derive(Debug, thiserror::Error)]
 pub enum ExternalFileLoadError {
     #[error("cannot open")]
     CannotOpen,
     #[error("cannot deserialize")]
-    CannotDeserialize
+    CannotDeserialize,
+    #[error("youtube channel requires `id` or `handle`")]
+    MissingChannelIdentifier
 }
 
 #[derive(Debug, Clone, Deserialize, Eq, PartialEq, Hash)]
@@ -71,6 +73,10 @@ impl LiverEntry {
         &self.channels
     }
 
+    pub fn as_mut_site(&mut self) -> &mut Vec<Channel> {
+        &mut self.channels
+    }
+
 
     pub fn breach_extraction_id(&self) -> NumId<LiverEntry> {
         self.id.to_owned()
@@ -88,8 +94,12 @@ impl LiverEntry {
         let _ = std::fs::File::open(path.as_ref())
             .context(ExternalFileLoadError::CannotOpen)?
             .read_to_string(&mut buf);
-        serde_json::from_str::<LiverEntry>(&buf)
-            .context(ExternalFileLoadError::CannotDeserialize)
+        let entry = serde_json::from_str::<LiverEntry>(&buf)
+            .context(ExternalFileLoadError::CannotDeserialize)?;
+        if entry.channels.iter().any(Channel::is_unidentified) {
+            return Err(ExternalFileLoadError::MissingChannelIdentifier.into())
+        }
+        Ok(entry)
     }
 }
 
@@ -97,7 +107,13 @@ impl LiverEntry {
 #[serde(tag = "site_name")]
 pub enum Channel {
     #[serde(rename = "Youtube")]
-    Youtube { id: StringId<Channel> },
+    Youtube {
+        #[serde(default, deserialize_with = "crate::ids::validated_optional")]
+        id: Option<StringId<Channel>>,
+        /// Resolved into `id` by channels API when `id` is absent.
+        #[serde(default, deserialize_with = "crate::ids::validated_optional")]
+        handle: Option<StringId<Handle>>
+    },
 
     #[serde(other)]
     Unsupported
@@ -106,24 +122,88 @@ pub enum Channel {
 impl Channel {
     pub fn as_youtube_id(&self) -> Option<StringId<Channel>> {
         match self {
-            Channel::Youtube { id} => Some(id.to_owned()),
+            Channel::Youtube { id: Some(id), .. } => Some(id.to_owned()),
+            _ => None
+        }
+    }
+
+    /// Returns handle only if the channel id is not known yet.
+    pub fn as_unresolved_youtube_handle(&self) -> Option<&StringId<Handle>> {
+        match self {
+            Channel::Youtube { id: None, handle } => handle.as_ref(),
             _ => None
         }
     }
+
+    pub fn resolve_youtube_handle(&mut self, resolved: StringId<Channel>) {
+        if let Channel::Youtube { id, .. } = self {
+            *id = Some(resolved);
+        }
+    }
+
+    fn is_unidentified(&self) -> bool {
+        matches!(self, Channel::Youtube { id: None, handle: None })
+    }
+}
+
+impl StringId<Channel> {
+    /// Uploads playlist of the channel. (`UC...` -> `UU...`)
+    pub fn uploads_playlist(&self) -> StringId<Playlist> {
+        let id = self.as_ref();
+        StringId::new(format!("UU{}", id.strip_prefix("UC").unwrap_or(id)))
+    }
+}
+
+impl IdFormat for Channel {
+    fn validate(id: &str) -> Result<(), IdFormatError> {
+        if !youtube::is_channel_id(id) {
+            return Err(IdFormatError::Channel(id.to_string()))
+        }
+        Ok(())
+    }
+}
+
+/// Marker for youtube `@handle`.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Handle;
+
+impl IdFormat for Handle {
+    fn validate(id: &str) -> Result<(), IdFormatError> {
+        if !youtube::is_handle(id) {
+            return Err(IdFormatError::Handle(id.to_string()))
+        }
+        Ok(())
+    }
+}
+
+/// Marker for youtube playlist id.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Playlist;
+
+impl IdFormat for Playlist {
+    fn validate(id: &str) -> Result<(), IdFormatError> {
+        if !youtube::is_playlist_id(id) {
+            return Err(IdFormatError::Playlist(id.to_string()))
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::models::{AffiliationEntry, LiverEntry};
+    use crate::ids::StringId;
+    use crate::models::{AffiliationEntry, Channel, LiverEntry};
 
     #[test]
     fn affiliation_load_test() {
         let suc = AffiliationEntry::load_from(".config/affiliation.json");
         let load_fail = AffiliationEntry::load_from(".config/affiliation.js");
+        let string_id = AffiliationEntry::load_from(".test/string_id_affiliation.json");
         let invalid = AffiliationEntry::load_from(".test/invalid_affiliation.json");
 
         assert!(suc.is_ok());
         assert!(load_fail.is_err());
+        assert!(string_id.is_ok());
         assert!(invalid.is_err());
     }
 
@@ -132,10 +212,32 @@ mod test {
         // 私の推し！
         let suc = LiverEntry::load_from(".config/hololive/nekomata_okayu.json");
         let load_fail = LiverEntry::load_from(".config/hololive/nekomata_okayu.js");
+        let string_id = LiverEntry::load_from(".test/string_id_liver.json");
         let invalid = LiverEntry::load_from(".test/invalid_liver.json");
 
         assert!(suc.is_ok());
         assert!(load_fail.is_err());
+        assert!(string_id.is_ok());
         assert!(invalid.is_err());
     }
+
+    #[test]
+    fn handle_liver_load_test() {
+        let handle = LiverEntry::load_from(".test/handle_liver.json").expect("");
+        let channel = handle.as_ref_site().first().expect("");
+
+        assert!(channel.as_youtube_id().is_none());
+        assert_eq!(channel.as_unresolved_youtube_handle().map(|handle| handle.as_ref()), Some("@nekomataokayu"));
+
+        let mut resolved = channel.to_owned();
+        resolved.resolve_youtube_handle(StringId::new("UCvaTdHTWBGv3MKj3KVqJVCw"));
+        assert!(resolved.as_unresolved_youtube_handle().is_none());
+        assert_eq!(resolved.as_youtube_id(), Some(StringId::<Channel>::new("UCvaTdHTWBGv3MKj3KVqJVCw")));
+    }
+
+    #[test]
+    fn uploads_playlist_test() {
+        let channel = StringId::<Channel>::new("UCvaTdHTWBGv3MKj3KVqJVCw");
+        assert_eq!(channel.uploads_playlist().as_ref(), "UUvaTdHTWBGv3MKj3KVqJVCw");
+    }
 }
\ No newline at end of file
@@ -4,7 +4,7 @@ use std::io::Write;
 use std::path::Path;
 use anyhow::{Result, Context};
 use git2::{AnnotatedCommit, AutotagOption, FetchOptions, RemoteCallbacks, Repository, ResetType};
-use crate::logger::Logger;
+use tracing::{error, info, info_span, warn};
 
 const DEFAULT_URL: &str = "https://github.com/ReiRokusanami0010/NekomataLibrary";
 const DEFAULT_PATH: &str = "./.config";
@@ -41,12 +41,12 @@ const REFERENCE_NAME: &str = "FETCH_HEAD";
 fn fetch_latest_contents(config_repo: &Repository) -> Result<AnnotatedCommit> {
     let mut fetch_callback = RemoteCallbacks::new();
     fetch_callback.transfer_progress(|status| {
-        let logger = Logger::new(Some("transfer"));
+        let _span = info_span!("transfer").entered();
         if status.received_objects() == status.total_objects() {
-            logger.info(format!("Resolving deltas {}/{}", status.indexed_deltas(), status.total_deltas()));
+            info!("Resolving deltas {}/{}", status.indexed_deltas(), status.total_deltas());
         } else if status.total_objects() > 0 {
-            logger.info(format!("Received {}/{} objects ({}) in {} bytes",
-                status.received_objects(), status.total_objects(), status.indexed_objects(), status.received_bytes()));
+            info!("Received {}/{} objects ({}) in {} bytes",
+                status.received_objects(), status.total_objects(), status.indexed_objects(), status.received_bytes());
         }
         std::io::stdout().flush().unwrap();
         true
@@ -59,20 +59,20 @@ fn fetch_latest_contents(config_repo: &Repository) -> Result<AnnotatedCommit> {
     let mut remote = config_repo.find_remote(REMOTE_NAME)
         .context(RepositoryManagementError::CommitFind("remote"))?;
 
-    let logger = Logger::new(Some("fetch"));
+    let _span = info_span!("fetch").entered();
 
-    logger.info(format!("Fetching {}", remote.name().unwrap()));
+    info!("Fetching {}", remote.name().unwrap());
 
     remote.fetch(&[REMOTE_BRANCH], Some(&mut fetch_option), None)
         .context(RepositoryManagementError::Fetch)?;
 
     let status = remote.stats();
     if status.local_objects() > 0 {
-        logger.info(format!("Received {}/{} objects in {} bytes (used {} local \\ objects)",
-            status.indexed_objects(), status.total_objects(), status.received_bytes(), status.local_objects()));
+        info!("Received {}/{} objects in {} bytes (used {} local \\ objects)",
+            status.indexed_objects(), status.total_objects(), status.received_bytes(), status.local_objects());
     } else {
-        logger.info(format!("Received {}/{} objects in {} bytes",
-            status.indexed_objects(), status.total_objects(), status.received_bytes()));
+        info!("Received {}/{} objects in {} bytes",
+            status.indexed_objects(), status.total_objects(), status.received_bytes());
     }
 
     let remote_head = config_repo.find_reference(REFERENCE_NAME)
@@ -85,7 +85,7 @@ fn fetch_latest_contents(config_repo: &Repository) -> Result<AnnotatedCommit> {
 const HEAD: &str = "HEAD";
 
 fn merge(config_repo: &Repository, local: &AnnotatedCommit, remote: &AnnotatedCommit) -> Result<()> {
-    let logger = Logger::new(Some("merge"));
+    let _span = info_span!("merge").entered();
 
     let local_tree = config_repo.find_commit(local.id())
         .context(RepositoryManagementError::CommitFind("local"))?.tree()
@@ -102,7 +102,7 @@ fn merge(config_repo: &Repository, local: &AnnotatedCommit, remote: &AnnotatedCo
         .context(RepositoryManagementError::Merge(103))?;
 
     if index.has_conflicts() {
-        logger.caut("conflict detected.");
+        warn!("conflict detected.");
         #[allow(unused_must_use)]
         config_repo.checkout_index(Some(&mut index), None)
             .context(RepositoryManagementError::Checkout)?;
@@ -126,7 +126,7 @@ fn merge(config_repo: &Repository, local: &AnnotatedCommit, remote: &AnnotatedCo
     config_repo.checkout_head(None)
         .context(RepositoryManagementError::Checkout)?;
 
-    logger.info("Merge pull successful.");
+    info!("Merge pull successful.");
 
     Ok(())
 }
@@ -134,10 +134,10 @@ fn merge(config_repo: &Repository, local: &AnnotatedCommit, remote: &AnnotatedCo
 fn update(config_repo: &Repository, remote_head: AnnotatedCommit) -> Result<()> {
     let analysis = config_repo.merge_analysis(&[&remote_head])
         .context(RepositoryManagementError::Analysis)?;
-    let logger = Logger::new(Some("update"));
+    let _span = info_span!("update").entered();
     if analysis.0.is_fast_forward() {
-        logger.error("fast forward does not impl... X/");
-        logger.error("please reset or remake dir config directory.");
+        error!("fast forward does not impl... X/");
+        error!("please reset or remake dir config directory.");
         unimplemented!()
     } else if analysis.0.is_normal() {
         let head = config_repo.head().expect("cannot get local head");
@@ -146,7 +146,7 @@ fn update(config_repo: &Repository, remote_head: AnnotatedCommit) -> Result<()>
         merge(config_repo, &local_head, &remote_head)
             .context(RepositoryManagementError::Merge(145))?;
     } else {
-        logger.info("no-op ;3");
+        info!("no-op ;3");
     }
 
     Ok(())